@@ -0,0 +1,113 @@
+//! Ticks 100k machine factories the way FactoryPlugin does, to check a tick fits in the fixed step.
+//! Run with `cargo bench --bench tick`, it fails when either factory is over its budget
+
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use factory::{bind_output, grid::{Grid, Rotation}, pipeline::recipe::Recipes, plugin::{FactoryPlugin, FactorySet}, sim::TICK_SECONDS, spawn_machine, ItemType};
+
+const MACHINES: usize = 100_000;
+/// Ticks run before measuring, so machines settle into what they'll keep doing. An uncoupled Producer fills its 50 item
+/// buffer in 500 ticks, and its Full alert comes 3000 ticks after that
+const WARMUP_TICKS: usize = 4000;
+const MEASURED_TICKS: usize = 100;
+/// One in this many rows of the idle factory is coupled up, the others stall and sleep
+const IDLE_ROWS_PER_BUSY: i32 = 1000;
+/// A sleeping machine should cost next to nothing, so the idle factory's crafting gets a sliver of the fixed step.
+/// Visiting every machine each tick instead of only the awake ones is enough to go over it
+const IDLE_CRAFT_BUDGET_FRACTION: f64 = 0.001;
+
+#[derive(Resource, Default)]
+/// When this tick's FactorySet::Craft started, and how long it has taken over every tick so far
+struct CraftTime {
+    started: Option<Instant>,
+    total: Duration,
+}
+
+fn start_craft(mut craft_time: ResMut<CraftTime>) {
+    craft_time.started = Some(Instant::now());
+}
+
+fn end_craft(mut craft_time: ResMut<CraftTime>) {
+    if let Some(started) = craft_time.started.take() {
+        craft_time.total += started.elapsed();
+    }
+}
+
+/// Rows of Producer -> Transformer -> Sink, three machines each. Only every `coupled_every`th row is coupled
+fn build_factory(app: &mut App, machines: usize, coupled_every: i32) {
+    let world = app.world_mut();
+    let recipes = world.resource::<Recipes>().clone();
+    let producer = recipes.get_producer(ItemType::Input).unwrap();
+    let transformer = recipes.get_transformer(ItemType::Storage).unwrap();
+    let sink = recipes.get_sink(ItemType::Storage).unwrap();
+
+    world.resource_scope(|world, mut grid: Mut<Grid>| {
+        let mut commands = world.commands();
+        for row in 0..(machines / 3) as i32 {
+            let y = -4 * row;
+            let src = spawn_machine(&mut commands, &mut grid, "Producer", producer, IVec2::new(0, y), Rotation::East).unwrap();
+            let middle = spawn_machine(&mut commands, &mut grid, "Transformer", transformer, IVec2::new(6, y), Rotation::East).unwrap();
+            let dest = spawn_machine(&mut commands, &mut grid, "Sink", sink, IVec2::new(12, y), Rotation::East).unwrap();
+
+            if row % coupled_every != 0 { continue; }
+            bind_output(&mut commands, src, middle, ItemType::Input);
+            bind_output(&mut commands, middle, dest, ItemType::Storage);
+        }
+    });
+    world.flush();
+}
+
+fn tick(app: &mut App) -> Duration {
+    let start = Instant::now();
+    app.world_mut().run_schedule(FixedUpdate);
+    start.elapsed()
+}
+
+/// Builds a factory, lets it settle and measures its ticks, giving back the slowest one and the mean time spent crafting
+fn measure(name: &str, coupled_every: i32) -> (Duration, Duration) {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, FactoryPlugin::headless()))
+        .init_resource::<CraftTime>()
+        .add_systems(FixedUpdate, (start_craft.after(FactorySet::Sense).before(FactorySet::Craft), end_craft.after(FactorySet::Craft).before(FactorySet::Transfer)));
+    app.finish();
+    app.cleanup();
+
+    let start = Instant::now();
+    build_factory(&mut app, MACHINES, coupled_every);
+    println!("{name}: built {MACHINES} machines in {:?}", start.elapsed());
+
+    for _ in 0..WARMUP_TICKS {
+        tick(&mut app);
+    }
+    app.world_mut().resource_mut::<CraftTime>().total = Duration::ZERO;
+    let mut ticks: Vec<Duration> = (0..MEASURED_TICKS).map(|_| tick(&mut app)).collect();
+    ticks.sort();
+
+    let mean = ticks.iter().sum::<Duration>() / MEASURED_TICKS as u32;
+    let max = ticks[MEASURED_TICKS - 1];
+    let crafting = app.world().resource::<CraftTime>().total / MEASURED_TICKS as u32;
+    println!("{name}: tick over {MEASURED_TICKS} ticks: mean {mean:?}, median {:?}, max {max:?}, crafting {crafting:?}", ticks[MEASURED_TICKS / 2]);
+    (max, crafting)
+}
+
+fn main() {
+    let step = Duration::from_secs_f64(TICK_SECONDS);
+    let (busy_max, _) = measure("Busy", 1);
+    let (_, idle_crafting) = measure("Idle", IDLE_ROWS_PER_BUSY);
+    let idle_budget = step.mul_f64(IDLE_CRAFT_BUDGET_FRACTION);
+
+    let mut over = false;
+    if busy_max > step {
+        eprintln!("Busy: slowest tick {busy_max:?} is over the {step:?} step");
+        over = true;
+    }
+    if idle_crafting > idle_budget {
+        eprintln!("Idle: crafting {idle_crafting:?} a tick is over its {idle_budget:?} budget");
+        over = true;
+    }
+    if over {
+        std::process::exit(1);
+    }
+    println!("Within budget");
+}
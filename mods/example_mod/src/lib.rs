@@ -0,0 +1,39 @@
+//! An example of extending the factory from outside the crate: a Recycler breaks Storages down into Scrap,
+//! and an Incinerator gets rid of it with its own per-tick behaviour
+
+use bevy::prelude::*;
+use factory::{modding::{CustomItem, CustomMachine, FactoryAppExt}, pipeline::{machine::{InputBank, ItemBuffer, MachineKind, MachineStats}, recipe::{ItemStack, Recipe}}, ItemType};
+
+pub static SCRAP: CustomItem = CustomItem { name: "Scrap", color: Color::srgb(0.45, 0.4, 0.35) };
+
+pub static RECYCLER: CustomMachine = CustomMachine { name: "Recycler", footprint: UVec2::new(3, 3), item: Some(ItemType::Transformer) };
+
+pub static INCINERATOR: CustomMachine = CustomMachine { name: "Incinerator", footprint: UVec2::new(2, 2), item: None };
+
+/// Most Scrap an Incinerator burns per tick
+pub const BURN_RATE: u64 = 2;
+
+pub struct ExampleModPlugin;
+
+impl Plugin for ExampleModPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_item(&SCRAP)
+            .add_machine_kind(&RECYCLER)
+            .add_machine_kind(&INCINERATOR)
+            .add_machine_behaviour(incinerate)
+            .add_recipe(Recipe::new(MachineKind::Custom(&RECYCLER), &[ItemStack::new(ItemType::Storage, 1)], &[ItemStack::new(ItemType::Custom(&SCRAP), 4)], 40))
+            .add_recipe(Recipe::new(MachineKind::Custom(&INCINERATOR), &[ItemStack::new(ItemType::Custom(&SCRAP), 1)], &[], 1));
+    }
+}
+
+/// Burns up to BURN_RATE Scrap per tick in every Incinerator
+pub fn incinerate(mut incinerator_query: Query<(&MachineKind, &InputBank, &mut MachineStats)>, mut buffer_query: Query<&mut ItemBuffer>) {
+    for (_, inputs, mut stats) in incinerator_query.iter_mut().filter(|(kind, ..)| **kind == MachineKind::Custom(&INCINERATOR)) {
+        let mut buffers = buffer_query.iter_many_mut(inputs.iter());
+        while let Some(mut buffer) = buffers.fetch_next() {
+            let burnt = buffer.current.min(BURN_RATE);
+            buffer.current -= burnt;
+            stats.items_consumed += burnt;
+        }
+    }
+}
@@ -0,0 +1,7 @@
+use example_mod::ExampleModPlugin;
+
+fn main() {
+    let mut app = factory::app();
+    app.add_plugins(ExampleModPlugin);
+    app.run();
+}
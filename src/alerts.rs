@@ -0,0 +1,284 @@
+use std::{cmp::Reverse, collections::{BinaryHeap, VecDeque}, time::Duration};
+
+use bevy::{ecs::entity::{hash_map::EntityHashMap, hash_set::EntityHashSet}, platform::collections::HashMap, prelude::*};
+
+use crate::{inspector::Inspected, pipeline::{machine::{MachineKind, MachineStatus}, validation::{DeadlockDetector, Deadlocked}, SimTick}, ui::{spawn_button, PANEL_COLOR}};
+
+pub const ALERTS_KEY: KeyCode = KeyCode::KeyN;
+/// Oldest notifications are dropped past this many
+const MAX_NOTIFICATIONS: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AlertKind {
+    /// MachineStatus::LacksInput
+    Starved,
+    /// MachineStatus::Full
+    OutputFull,
+    /// Waiting on each other in a loop, see detect_deadlocks
+    Deadlocked,
+}
+
+impl AlertKind {
+    /// Alert a machine raises once it's been stuck with `status` for long enough, None if it's not stuck
+    pub fn of(status: MachineStatus) -> Option<Self> {
+        match status {
+            MachineStatus::LacksInput => Some(AlertKind::Starved),
+            MachineStatus::Full => Some(AlertKind::OutputFull),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// How long a machine can be stuck before it raises an alert, None for never
+pub struct Thresholds {
+    pub starved: Option<Duration>,
+    pub full: Option<Duration>,
+}
+
+impl Thresholds {
+    fn get(&self, kind: AlertKind) -> Option<Duration> {
+        match kind {
+            AlertKind::Starved => self.starved,
+            AlertKind::OutputFull => self.full,
+            AlertKind::Deadlocked => None,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+/// When machines raise alerts and how they're put in the feed
+pub struct AlertConfig {
+    /// Thresholds for machine kinds without their own
+    pub default: Thresholds,
+    pub kinds: HashMap<MachineKind, Thresholds>,
+    /// A machine doesn't raise the same alert again within this long of the last time
+    pub cooldown: Duration,
+    /// Alerts of the same kind from the same kind of machine within this long of each other go into one notification
+    pub grouping: Duration,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        let default = Thresholds { starved: Some(Duration::from_secs(60)), full: Some(Duration::from_secs(5 * 60)) };
+        // Sinks and storages wait for whatever comes, that's their job
+        let waiting = Thresholds { starved: None, ..default };
+        Self {
+            default,
+            kinds: HashMap::from_iter([(MachineKind::Sink, waiting), (MachineKind::Storage, waiting)]),
+            cooldown: Duration::from_secs(5 * 60),
+            grouping: Duration::from_secs(10),
+        }
+    }
+}
+
+impl AlertConfig {
+    pub fn thresholds(&self, kind: MachineKind) -> Thresholds {
+        self.kinds.get(&kind).copied().unwrap_or(self.default)
+    }
+
+    pub fn set(&mut self, kind: MachineKind, thresholds: Thresholds) {
+        self.kinds.insert(kind, thresholds);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// One entry in the feed, for one alert or several grouped together
+pub struct Notification {
+    /// Tick the latest alert in it was raised on
+    pub tick: u64,
+    pub kind: AlertKind,
+    /// Kind of the machines that raised it, None for deadlocks, which can span several
+    pub machine_kind: Option<MachineKind>,
+    pub machines: Vec<Entity>,
+    /// How long the machines had been stuck when they raised it
+    pub after: Duration,
+}
+
+impl std::fmt::Display for Notification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let machines = match (self.machine_kind, self.machines.len()) {
+            (Some(kind), 1) => format!("{kind:?}"),
+            (Some(kind), count) => format!("{count} {kind:?}s"),
+            (None, count) => format!("{count} machines"),
+        };
+        let after = match self.after.as_secs() {
+            seconds if seconds >= 120 && seconds % 60 == 0 => format!("{} min", seconds / 60),
+            seconds => format!("{seconds} s"),
+        };
+
+        match self.kind {
+            AlertKind::Starved => write!(f, "{machines} waiting for input for {after}"),
+            AlertKind::OutputFull => write!(f, "{machines} output full for {after}"),
+            AlertKind::Deadlocked => write!(f, "{machines} deadlocked on each other for {after}"),
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+/// The alert feed, oldest first
+pub struct Notifications {
+    pub feed: VecDeque<Notification>,
+    /// Tick each machine last raised each kind of alert on, for the cooldown
+    raised: HashMap<(Entity, AlertKind), u64>,
+}
+
+impl Notifications {
+    /// Puts `machine`'s alert in the feed, with a notification of the same kind from within `grouping` ticks if there
+    /// is one. Nothing happens if the machine raised the same alert within `cooldown` ticks
+    fn raise(&mut self, tick: u64, machine: Entity, machine_kind: MachineKind, kind: AlertKind, after: Duration, (cooldown, grouping): (u64, u64)) {
+        if self.raised.get(&(machine, kind)).is_some_and(|raised| tick < raised + cooldown) { return; }
+        self.raised.insert((machine, kind), tick);
+
+        // Others may have been raised in between, deadlocks or other kinds of machine
+        let mut recent = self.feed.iter_mut().rev().take_while(|notification| tick <= notification.tick + grouping);
+        if let Some(group) = recent.find(|notification| notification.kind == kind && notification.machine_kind == Some(machine_kind)) {
+            group.tick = tick;
+            group.machines.push(machine);
+            group.after = group.after.max(after);
+            return;
+        }
+        self.push(Notification { tick, kind, machine_kind: Some(machine_kind), machines: vec![machine], after });
+    }
+
+    /// Forgets the alerts `machine` raised, once it's gone
+    fn forget(&mut self, machine: Entity) {
+        for kind in [AlertKind::Starved, AlertKind::OutputFull, AlertKind::Deadlocked] {
+            self.raised.remove(&(machine, kind));
+        }
+    }
+
+    fn push(&mut self, notification: Notification) {
+        self.feed.push_back(notification);
+        if self.feed.len() > MAX_NOTIFICATIONS {
+            self.feed.pop_front();
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+/// What each stuck machine is stuck on and since when, and when to look at it next for an alert
+pub struct StatusHistory {
+    stuck_since: EntityHashMap<(AlertKind, u64)>,
+    /// Stuck machines by the tick their alert is due on, soonest first
+    due: BinaryHeap<Reverse<(u64, Entity)>>,
+    /// When each machine in `due` is due. It's queued again only when what it's stuck on now comes due sooner,
+    /// not every time it gets stuck
+    queued: EntityHashMap<u64>,
+}
+
+/// Ticks `duration` takes at the fixed timestep, rounded up
+fn ticks(duration: Duration, time: &Time<Fixed>) -> u64 {
+    duration.div_duration_f64(time.timestep()).ceil() as u64
+}
+
+#[allow(clippy::too_many_arguments)]
+/// Keeps each machine's StatusHistory, and raises an alert once one has been LacksInput or Full for longer than its
+/// kind's AlertConfig thresholds. Deadlocks found by detect_deadlocks go in the feed as well
+pub fn raise_alerts(tick: Res<SimTick>, time: Res<Time<Fixed>>, (config, detector): (Res<AlertConfig>, Res<DeadlockDetector>), (mut history, mut notifications): (ResMut<StatusHistory>, ResMut<Notifications>), status_query: Query<(Entity, &MachineStatus), Changed<MachineStatus>>, mut removed: RemovedComponents<MachineStatus>, kind_query: Query<&MachineKind>, mut deadlocked: MessageReader<Deadlocked>) {
+    for (machine, status) in &status_query {
+        let Some(alert) = AlertKind::of(*status) else {
+            history.stuck_since.remove(&machine);
+            continue;
+        };
+        if history.stuck_since.get(&machine).is_some_and(|(stuck_on, _)| *stuck_on == alert) { continue; }
+
+        history.stuck_since.insert(machine, (alert, tick.0));
+        let Ok(kind) = kind_query.get(machine) else { continue };
+        if let Some(threshold) = config.thresholds(*kind).get(alert) {
+            let due = tick.0 + ticks(threshold, &time);
+            if history.queued.get(&machine).is_none_or(|queued| due < *queued) {
+                history.queued.insert(machine, due);
+                history.due.push(Reverse((due, machine)));
+            }
+        }
+    }
+    let removed: EntityHashSet = removed.read().collect();
+    if !removed.is_empty() {
+        for machine in &removed {
+            history.stuck_since.remove(machine);
+            history.queued.remove(machine);
+            notifications.forget(*machine);
+        }
+        history.due.retain(|Reverse((_, machine))| !removed.contains(machine));
+    }
+
+    let limits = (ticks(config.cooldown, &time), ticks(config.grouping, &time));
+    while let Some(&Reverse((due, machine))) = history.due.peek() && due <= tick.0 {
+        history.due.pop();
+        // Queued again for sooner since
+        if history.queued.get(&machine) != Some(&due) { continue; }
+        history.queued.remove(&machine);
+        let Some(&(alert, since)) = history.stuck_since.get(&machine) else { continue };
+        let Ok(kind) = kind_query.get(machine) else { continue };
+        let Some(threshold) = config.thresholds(*kind).get(alert) else { continue };
+
+        // Machines that got going and got stuck again since, or the threshold was raised since they were queued
+        let threshold_ticks = ticks(threshold, &time);
+        if tick.0 - since < threshold_ticks {
+            history.due.push(Reverse((since + threshold_ticks, machine)));
+            history.queued.insert(machine, since + threshold_ticks);
+            continue;
+        }
+        let after = time.timestep().mul_f64((tick.0 - since) as f64);
+        notifications.raise(tick.0, machine, *kind, alert, after, limits);
+    }
+
+    for deadlock in deadlocked.read() {
+        let after = time.timestep().mul_f64(detector.ticks as f64);
+        notifications.push(Notification { tick: deadlock.tick, kind: AlertKind::Deadlocked, machine_kind: None, machines: deadlock.machines.clone(), after });
+    }
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct AlertFeed;
+
+#[derive(Component, Clone, Copy, Debug)]
+/// Inspects the machine and moves the camera to it
+pub struct AlertButton(pub Entity);
+
+pub fn setup_alert_feed(mut commands: Commands) {
+    commands.spawn((
+        AlertFeed,
+        Node {
+            display: Display::None,
+            position_type: PositionType::Absolute,
+            flex_direction: FlexDirection::Column,
+            left: percent(35),
+            top: px(10),
+            padding: UiRect::all(px(6)),
+            row_gap: px(5),
+            ..default()
+        },
+        BackgroundColor(PANEL_COLOR),
+        Interaction::default(),
+    ));
+}
+
+/// Lists the notifications newest first, each a button for the machine that raised it. Hidden while there are none,
+/// or after ALERTS_KEY until it's pressed again
+pub fn update_alert_feed(mut commands: Commands, keys: Res<ButtonInput<KeyCode>>, notifications: Res<Notifications>, mut hidden: Local<bool>, mut feed_query: Query<(Entity, &mut Node), With<AlertFeed>>) {
+    let Ok((feed, mut node)) = feed_query.single_mut() else { return };
+    if keys.just_pressed(ALERTS_KEY) {
+        *hidden = !*hidden;
+    }
+    node.display = if *hidden || notifications.feed.is_empty() { Display::None } else { Display::Flex };
+    if !notifications.is_changed() { return; }
+
+    commands.entity(feed).despawn_related::<Children>().with_children(|builder| {
+        for notification in notifications.feed.iter().rev() {
+            let Some(machine) = notification.machines.first() else { continue };
+            spawn_button(builder, notification.to_string(), AlertButton(*machine));
+        }
+    });
+}
+
+pub fn handle_alert_buttons(mut inspected: ResMut<Inspected>, button_query: Query<(&Interaction, &AlertButton), Changed<Interaction>>, machine_query: Query<&Transform, (With<MachineKind>, Without<Camera2d>)>, mut camera_query: Query<&mut Transform, With<Camera2d>>) {
+    for (_, AlertButton(machine)) in button_query.iter().filter(|(interaction, _)| **interaction == Interaction::Pressed) {
+        let Ok(transform) = machine_query.get(*machine) else { continue };
+        if let Ok(mut camera) = camera_query.single_mut() {
+            camera.translation = transform.translation.truncate().extend(camera.translation.z);
+        }
+        inspected.0 = Some(*machine);
+    }
+}
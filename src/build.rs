@@ -0,0 +1,235 @@
+use bevy::prelude::*;
+
+use crate::{camera::Cursor, edit::{record_couple, record_move, record_spawn}, grid::{machine_size, Grid, GridPos, PlacementError, Rotation}, inventory::Inventory, pipeline::{machine::{BuildCost, MachineInput, MachineKind, MachineOutput}, recipe::{ItemStack, Recipe, Recipes}}, research::Research, spawn_machine, ui::{pointer_over_ui, spawn_button, PANEL_COLOR}, ItemType};
+
+pub const ROTATE_KEY: KeyCode = KeyCode::KeyR;
+const GHOST_COLOR: Color = Color::linear_rgba(0.2, 1.0, 0.2, 0.8);
+const BLOCKED_COLOR: Color = Color::linear_rgba(1.0, 0.2, 0.2, 0.8);
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+/// The recipe being placed, if any, and which way it'll face
+pub struct BuildTool {
+    pub recipe: Option<Recipe>,
+    pub rotation: Rotation,
+}
+
+impl BuildTool {
+    /// Where a machine would go with the cursor at `position`, and how many cells it would take up
+    pub fn placement(&self, recipe: &Recipe, position: Vec2) -> (IVec2, UVec2) {
+        let footprint = self.rotation.rotate_footprint(recipe.machine_kind.footprint());
+        (Grid::snap_footprint(position, footprint), footprint)
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct BuildRules {
+    /// Progression mode: placing a machine uses up one item of its kind from the Inventory
+    pub consume_items: bool,
+}
+
+impl BuildRules {
+    /// What placing a machine of `kind` costs under these rules
+    pub fn cost(&self, kind: MachineKind) -> Option<ItemStack> {
+        kind.item().filter(|_| self.consume_items).map(|item_type| ItemStack::new(item_type, 1))
+    }
+}
+
+impl MachineKind {
+    /// The item a machine of this kind is built from, if it takes one
+    pub fn item(&self) -> Option<ItemType> {
+        match self {
+            MachineKind::Producer => Some(ItemType::Producer),
+            MachineKind::Transformer => Some(ItemType::Transformer),
+            MachineKind::Combinator => Some(ItemType::Combinator),
+            MachineKind::Separator => Some(ItemType::Separator),
+            MachineKind::Storage => Some(ItemType::Storage),
+            MachineKind::Sink | MachineKind::Lab => None,
+            MachineKind::Custom(machine) => machine.item,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuildError {
+    Placement(PlacementError),
+    /// Progression mode is on and the Inventory has none of this item
+    MissingItem(ItemType),
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+/// What the left mouse button is dragging around the world, if anything
+pub enum Dragging {
+    #[default]
+    Nothing,
+    /// A machine being moved, `offset` from where it started
+    Machine { machine: Entity, offset: Vec2 },
+    /// An OutputConnector being dragged onto an InputConnector to couple them
+    Output(Entity),
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct BuildPalette;
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PaletteButton(pub Recipe);
+
+pub fn setup_build_palette(mut commands: Commands, recipes: Res<Recipes>) {
+    commands.spawn((
+        BuildPalette,
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: px(0),
+            left: px(0),
+            flex_wrap: FlexWrap::Wrap,
+            column_gap: px(5),
+            row_gap: px(5),
+            padding: UiRect::all(px(5)),
+            ..default()
+        },
+        BackgroundColor(PANEL_COLOR),
+        Interaction::default(),
+    )).with_children(|builder| {
+        for recipe in &recipes.inner {
+            spawn_button(builder, recipe.name(), PaletteButton(*recipe));
+        }
+    });
+}
+
+/// Only shows researched recipes, along with how many of each machine can be built from the Inventory while in progression mode
+pub fn update_palette(rules: Res<BuildRules>, inventory: Res<Inventory>, research: Res<Research>, mut button_query: Query<(&PaletteButton, &Children, &mut Node)>, mut text_query: Query<&mut Text>) {
+    if !rules.is_changed() && !inventory.is_changed() && !research.is_changed() { return; }
+
+    for (PaletteButton(recipe), children, mut node) in &mut button_query {
+        node.display = if research.is_unlocked(recipe) { Display::Flex } else { Display::None };
+
+        let Some(label) = children.iter().find(|child| text_query.contains(*child)) else { continue };
+        let Ok(mut text) = text_query.get_mut(label) else { continue };
+        text.0 = match rules.cost(recipe.machine_kind) {
+            Some(cost) => format!("{} ({})", recipe.name(), inventory.count(cost.item_type) / cost.amount),
+            None => recipe.name(),
+        };
+    }
+}
+
+pub fn handle_palette_buttons(mut tool: ResMut<BuildTool>, button_query: Query<(&Interaction, &PaletteButton), Changed<Interaction>>) {
+    for (_, PaletteButton(recipe)) in button_query.iter().filter(|(interaction, _)| **interaction == Interaction::Pressed) {
+        tool.recipe = if tool.recipe == Some(*recipe) { None } else { Some(*recipe) };
+    }
+}
+
+pub fn build_controls(keys: Res<ButtonInput<KeyCode>>, mut tool: ResMut<BuildTool>) {
+    if keys.just_pressed(ROTATE_KEY) {
+        tool.rotation = tool.rotation.next();
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        tool.recipe = None;
+    }
+}
+
+pub fn place_machine(mut commands: Commands, tool: Res<BuildTool>, mouse: Res<ButtonInput<MouseButton>>, cursor: Cursor, interaction_query: Query<&Interaction>) {
+    if !mouse.just_pressed(MouseButton::Left) || pointer_over_ui(&interaction_query) { return; }
+    let (Some(recipe), Some(position)) = (tool.recipe, cursor.world_position()) else { return };
+
+    let (cell, _) = tool.placement(&recipe, position);
+    record_spawn(&mut commands, recipe, cell, tool.rotation);
+}
+
+/// Places a machine the way the player does. In progression mode this takes its item out of the Inventory,
+/// and records it as the BuildCost so deconstructing gives it back
+pub fn build_machine(world: &mut World, recipe: Recipe, cell: IVec2, rotation: Rotation) -> Result<Entity, BuildError> {
+    let cost = world.resource::<BuildRules>().cost(recipe.machine_kind);
+    if let Some(cost) = cost && world.resource::<Inventory>().count(cost.item_type) < cost.amount {
+        return Err(BuildError::MissingItem(cost.item_type));
+    }
+
+    let machine = world.resource_scope(|world, mut grid: Mut<Grid>| {
+        spawn_machine(&mut world.commands(), &mut grid, &format!("{:?}", recipe.machine_kind), recipe, cell, rotation)
+    }).map_err(BuildError::Placement)?;
+    world.flush();
+
+    if let Some(cost) = cost {
+        world.resource_mut::<Inventory>().take(cost.item_type, cost.amount);
+        world.entity_mut(machine).insert(BuildCost(vec![cost]));
+    }
+
+    Ok(machine)
+}
+
+/// Outlines where the machine would go, pointing towards its output side
+pub fn draw_build_ghost(mut gizmos: Gizmos, tool: Res<BuildTool>, grid: Res<Grid>, rules: Res<BuildRules>, inventory: Res<Inventory>, cursor: Cursor) {
+    let (Some(recipe), Some(position)) = (tool.recipe, cursor.world_position()) else { return };
+
+    let (cell, footprint) = tool.placement(&recipe, position);
+    let area = Grid::rect(cell, footprint);
+    let affordable = rules.cost(recipe.machine_kind).is_none_or(|cost| inventory.count(cost.item_type) >= cost.amount);
+    let color = if affordable && grid.check(cell, footprint).is_ok() { GHOST_COLOR } else { BLOCKED_COLOR };
+    let reach = machine_size(recipe.machine_kind, tool.rotation) * 0.5 * tool.rotation.facing().as_vec2();
+
+    gizmos.rect_2d(area.center(), area.size(), color);
+    gizmos.arrow_2d(area.center(), area.center() + reach, color);
+}
+
+/// Dragging an OutputConnector starts coupling it, dragging anything else on a machine starts moving the machine
+pub fn start_drag(drag: On<Pointer<DragStart>>, mut dragging: ResMut<Dragging>, output_query: Query<(), With<MachineOutput>>, input_query: Query<(), With<MachineInput>>) {
+    if drag.button != PointerButton::Primary { return; }
+    let target = drag.original_event_target();
+
+    *dragging = if output_query.contains(target) {
+        Dragging::Output(target)
+    } else if input_query.contains(target) {
+        Dragging::Nothing
+    } else {
+        Dragging::Machine { machine: drag.entity, offset: Vec2::ZERO }
+    };
+}
+
+pub fn track_drag(drag: On<Pointer<Drag>>, mut dragging: ResMut<Dragging>, camera_query: Query<&Projection, With<Camera2d>>) {
+    let Dragging::Machine { offset, .. } = &mut *dragging else { return };
+    let Ok(Projection::Orthographic(projection)) = camera_query.single() else { return };
+
+    // Screen y points down, world y points up
+    *offset = Vec2::new(drag.distance.x, -drag.distance.y) * projection.scale;
+}
+
+/// Finishes moving a machine, snapping it to the cells it was dropped over
+pub fn end_drag(drag: On<Pointer<DragEnd>>, mut commands: Commands, mut dragging: ResMut<Dragging>, machine_query: Query<(&Transform, &MachineKind, &Rotation, &GridPos)>) {
+    if drag.button != PointerButton::Primary { return; }
+
+    if let Dragging::Machine { machine, offset } = *dragging
+        && let Ok((transform, kind, rotation, GridPos(origin))) = machine_query.get(machine) {
+        let cell = Grid::snap_footprint(transform.translation.truncate() + offset, rotation.rotate_footprint(kind.footprint()));
+        if cell != *origin {
+            record_move(&mut commands, machine, cell, *rotation);
+        }
+    }
+    *dragging = Dragging::Nothing;
+}
+
+/// Couples an OutputConnector dropped onto an InputConnector
+pub fn couple_on_drop(drop: On<Pointer<DragDrop>>, mut commands: Commands, output_query: Query<(), With<MachineOutput>>, input_query: Query<(), With<MachineInput>>) {
+    let input = drop.original_event_target();
+    if drop.button == PointerButton::Primary && output_query.contains(drop.dropped) && input_query.contains(input) {
+        record_couple(&mut commands, drop.dropped, input);
+    }
+}
+
+/// Outlines where a machine being moved would end up, or draws the coupling being dragged out
+pub fn draw_drag_ghost(mut gizmos: Gizmos, dragging: Res<Dragging>, grid: Res<Grid>, cursor: Cursor, machine_query: Query<(&Transform, &MachineKind, &Rotation, &GridPos)>, connector_query: Query<&GlobalTransform>) {
+    match *dragging {
+        Dragging::Nothing => {},
+        Dragging::Machine { machine, offset } => {
+            let Ok((transform, kind, rotation, GridPos(origin))) = machine_query.get(machine) else { return };
+            let footprint = rotation.rotate_footprint(kind.footprint());
+            let cell = Grid::snap_footprint(transform.translation.truncate() + offset, footprint);
+            if cell == *origin { return; }
+
+            let area = Grid::rect(cell, footprint);
+            let color = if grid.check_except(cell, footprint, machine).is_ok() { GHOST_COLOR } else { BLOCKED_COLOR };
+            gizmos.rect_2d(area.center(), area.size(), color);
+        },
+        Dragging::Output(output) => {
+            let (Ok(start), Some(end)) = (connector_query.get(output), cursor.world_position()) else { return };
+            gizmos.line_2d(start.translation().truncate(), end, GHOST_COLOR);
+        },
+    }
+}
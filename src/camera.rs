@@ -0,0 +1,92 @@
+use bevy::{ecs::system::SystemParam, input::mouse::{AccumulatedMouseMotion, AccumulatedMouseScroll, MouseScrollUnit}, prelude::*, window::PrimaryWindow};
+
+use crate::{grid::{machine_size, Rotation}, pipeline::machine::{InputBufferText, MachineKind, OutputBufferText}};
+
+const ZOOM_STEP: f32 = 0.9;
+const MIN_ZOOM: f32 = 0.1;
+const MAX_ZOOM: f32 = 10.0;
+/// Pixels of a pixel-based scroll event that count as one line
+const PIXELS_PER_LINE: f32 = 16.0;
+/// Zoomed out further than this, buffer contents are too small to read so they get hidden
+const DETAIL_ZOOM: f32 = 1.5;
+/// Leaves some room around the machines when framing them
+const FRAME_MARGIN: f32 = 1.2;
+
+pub const FRAME_ALL_KEY: KeyCode = KeyCode::KeyF;
+
+#[derive(SystemParam)]
+pub struct Cursor<'w, 's> {
+    window_query: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+    camera_query: Query<'w, 's, (&'static Camera, &'static GlobalTransform)>,
+}
+
+impl Cursor<'_, '_> {
+    /// Where the cursor is pointing in the world, if it's over the window
+    pub fn world_position(&self) -> Option<Vec2> {
+        let position = self.window_query.single().ok()?.cursor_position()?;
+        let (camera, camera_transform) = self.camera_query.single().ok()?;
+
+        camera.viewport_to_world_2d(camera_transform, position).ok()
+    }
+}
+
+pub fn pan_camera(buttons: Res<ButtonInput<MouseButton>>, motion: Res<AccumulatedMouseMotion>, mut camera_query: Query<(&mut Transform, &Projection), With<Camera2d>>) {
+    if !buttons.any_pressed([MouseButton::Middle, MouseButton::Right]) || motion.delta == Vec2::ZERO { return; }
+    let Ok((mut transform, Projection::Orthographic(projection))) = camera_query.single_mut() else { return };
+
+    // Screen y points down, world y points up
+    transform.translation += Vec3::new(-motion.delta.x, motion.delta.y, 0.0) * projection.scale;
+}
+
+/// Zooms towards the cursor, so the point under it stays put
+pub fn zoom_camera(scroll: Res<AccumulatedMouseScroll>, window_query: Query<&Window, With<PrimaryWindow>>, mut camera_query: Query<(&Camera, &GlobalTransform, &mut Transform, &mut Projection)>) {
+    let lines = match scroll.unit {
+        MouseScrollUnit::Line => scroll.delta.y,
+        MouseScrollUnit::Pixel => scroll.delta.y / PIXELS_PER_LINE,
+    };
+    if lines == 0.0 { return; }
+
+    let Ok((camera, camera_transform, mut transform, mut projection)) = camera_query.single_mut() else { return };
+    let Projection::Orthographic(projection) = &mut *projection else { return };
+
+    let cursor = window_query.single().ok()
+        .and_then(|window| window.cursor_position())
+        .and_then(|position| camera.viewport_to_world_2d(camera_transform, position).ok());
+    let old_scale = projection.scale;
+    projection.scale = (projection.scale * ZOOM_STEP.powf(lines)).clamp(MIN_ZOOM, MAX_ZOOM);
+
+    if let Some(cursor) = cursor {
+        let center = cursor + (transform.translation.truncate() - cursor) * (projection.scale / old_scale);
+        transform.translation = center.extend(transform.translation.z);
+    }
+}
+
+pub fn setup_camera(mut commands: Commands) {
+    commands.spawn(Camera2d);
+}
+
+/// Centers the camera on every machine and zooms so they all fit in the window
+pub fn frame_all(window_query: Query<&Window, With<PrimaryWindow>>, machine_query: Query<(&Transform, &MachineKind, Option<&Rotation>), Without<Camera2d>>, mut camera_query: Query<(&mut Transform, &mut Projection), With<Camera2d>>) {
+    let Some(bounds) = machine_query.iter().map(|(transform, kind, rotation)| Rect::from_center_size(transform.translation.truncate(), machine_size(*kind, rotation.copied().unwrap_or_default()))).reduce(|acc, rect| acc.union(rect)) else { return };
+    let Ok(window) = window_query.single() else { return };
+    let Ok((mut transform, mut projection)) = camera_query.single_mut() else { return };
+    let Projection::Orthographic(projection) = &mut *projection else { return };
+
+    let fit = bounds.size() / window.size();
+    projection.scale = (fit.max_element() * FRAME_MARGIN).clamp(MIN_ZOOM, MAX_ZOOM);
+    transform.translation = bounds.center().extend(transform.translation.z);
+}
+
+/// Hides buffer contents when zoomed out too far to read them
+pub fn update_label_detail(camera_query: Query<&Projection, With<Camera2d>>, machine_query: Query<(&InputBufferText, &OutputBufferText)>, mut visibility_query: Query<&mut Visibility>) {
+    let Ok(Projection::Orthographic(projection)) = camera_query.single() else { return };
+    let detail = if projection.scale > DETAIL_ZOOM { Visibility::Hidden } else { Visibility::Inherited };
+
+    for (input_label, output_label) in &machine_query {
+        for label in [input_label.0, output_label.0] {
+            if let Ok(mut visibility) = visibility_query.get_mut(label) {
+                visibility.set_if_neq(detail);
+            }
+        }
+    }
+}
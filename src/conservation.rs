@@ -0,0 +1,291 @@
+use bevy::{ecs::entity::hash_map::EntityHashMap, platform::collections::HashMap, prelude::*};
+
+use crate::{inventory::{Deposit, Inventory}, objectives::Deliveries, pipeline::{machine::{input_buffers, output_buffers, MachineKind, MachineStats, MachineStatus}, recipe::{ItemStack, Recipe}, IoBuffer, SimTick}, ItemType};
+
+#[derive(Clone, Debug)]
+/// Items that appeared, disappeared or didn't fit where they are
+pub struct Violation {
+    pub tick: u64,
+    /// Machine whose buffers are off, None when it's only the factory's total that doesn't add up
+    pub machine: Option<Entity>,
+    /// System that ran just before the violation was found
+    pub system: &'static str,
+    pub problem: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.machine {
+            Some(machine) => write!(f, "Tick {}, {} on {machine}: {}", self.tick, self.system, self.problem),
+            None => write!(f, "Tick {}, {}: {}", self.tick, self.system, self.problem),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// A machine's buffers and ledger entries as of the last check
+struct Holdings {
+    kind: MachineKind,
+    recipe: Recipe,
+    status: MachineStatus,
+    stats: MachineStats,
+    deposit: bool,
+    inputs: Vec<IoBuffer>,
+    outputs: Vec<IoBuffer>,
+}
+
+#[derive(Clone, Copy, Debug)]
+/// Items fit_buffers moved out of a buffer into the Inventory because they no longer fit
+struct Overflow {
+    machine: Entity,
+    output: bool,
+    slot: usize,
+    stack: ItemStack,
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+/// Checks every tick that items only appear and disappear the way recipes, sinks, storages and labs say they do,
+/// and that no buffer holds more than it can. Only there with FactoryPlugin::check_conservation
+pub struct Conservation {
+    machines: EntityHashMap<Holdings>,
+    deliveries: Deliveries,
+    inventory: Inventory,
+    /// Ledger entries for overflow since the last check, see record_overflow
+    overflow: Vec<Overflow>,
+    pub violations: Vec<Violation>,
+}
+
+/// Items per type across `buffers`
+fn totals(buffers: &[IoBuffer]) -> HashMap<ItemType, i64> {
+    let mut totals = HashMap::default();
+    for buffer in buffers {
+        *totals.entry(buffer.item_type).or_default() += buffer.buffer.current as i64;
+    }
+    totals
+}
+
+/// Change per item type from `before` to `after`, leaving out what didn't change
+fn delta(before: &[IoBuffer], after: &[IoBuffer]) -> HashMap<ItemType, i64> {
+    let mut delta = totals(after);
+    for (item_type, count) in totals(before) {
+        *delta.entry(item_type).or_default() -= count;
+    }
+    delta.retain(|_, change| *change != 0);
+    delta
+}
+
+/// `stacks` times `times`, per item type
+fn ledger(stacks: &[Option<ItemStack>; 4], times: i64) -> HashMap<ItemType, i64> {
+    let mut ledger: HashMap<ItemType, i64> = HashMap::default();
+    for stack in stacks.iter().filter_map(|s| *s) {
+        *ledger.entry(stack.item_type).or_default() += stack.amount as i64 * times;
+    }
+    ledger.retain(|_, change| *change != 0);
+    ledger
+}
+
+/// Changes a check expects of a machine's input and output buffers, per item type
+type Expected = (HashMap<ItemType, i64>, HashMap<ItemType, i64>);
+/// Problems with the factory as a whole, or with machines the expectation can't tell about
+type Problems = Vec<(Option<Entity>, String)>;
+
+impl Conservation {
+    /// Notes `stack` left `machine`'s input or output buffer in `slot` for the Inventory, when fit_buffers shrinks it
+    pub fn record_overflow(&mut self, machine: Entity, output: bool, slot: usize, stack: ItemStack) {
+        self.overflow.push(Overflow { machine, output, slot, stack });
+    }
+
+    /// Moves the recorded overflow in the last check's snapshot, so the next one only sees what happened otherwise
+    fn settle_overflow(&mut self) {
+        for Overflow { machine, output, slot, stack } in std::mem::take(&mut self.overflow) {
+            let Some(holdings) = self.machines.get_mut(&machine) else { continue };
+            let buffers = if output { &mut holdings.outputs } else { &mut holdings.inputs };
+            if let Some(buffer) = buffers.get_mut(slot) {
+                buffer.buffer.current = buffer.buffer.current.saturating_sub(stack.amount);
+            }
+            self.inventory.add(stack.item_type, stack.amount);
+        }
+    }
+
+    fn snapshot(world: &mut World) -> EntityHashMap<Holdings> {
+        world.query::<(Entity, &MachineKind, &Recipe, &MachineStatus, &MachineStats, Has<Deposit>)>().iter(world)
+            .map(|(machine, kind, recipe, status, stats, deposit)| (machine, Holdings {
+                kind: *kind,
+                recipe: *recipe,
+                status: *status,
+                stats: *stats,
+                deposit,
+                inputs: input_buffers(world, machine),
+                outputs: output_buffers(world, machine),
+            }))
+            .collect()
+    }
+
+    /// Compares every machine's buffers against the last check, then remembers them for the next one
+    fn check(world: &mut World, system: &'static str, expectation: impl Fn(&Holdings, &Holdings) -> Option<Expected>, totals: impl Fn(&World, &Conservation, &EntityHashMap<Holdings>) -> Problems) {
+        let tick = world.resource::<SimTick>().0;
+        world.resource_mut::<Conservation>().settle_overflow();
+        let after = Self::snapshot(world);
+        let conservation = world.resource::<Conservation>();
+        let mut violations = Vec::new();
+
+        for (machine, now) in after.iter() {
+            for buffer in now.inputs.iter().chain(now.outputs.iter()).filter(|buffer| buffer.buffer.current > buffer.buffer.max) {
+                violations.push(Violation { tick, machine: Some(*machine), system, problem: format!("holds {} {:?} with room for {}", buffer.buffer.current, buffer.item_type, buffer.buffer.max) });
+            }
+
+            // Machines built or rebuilt since are left for the next check
+            let Some(before) = conservation.machines.get(machine).filter(|before| before.recipe == now.recipe) else { continue };
+            let Some((inputs, outputs)) = expectation(before, now) else { continue };
+            let (input_delta, output_delta) = (delta(&before.inputs, &now.inputs), delta(&before.outputs, &now.outputs));
+            if input_delta != inputs {
+                violations.push(Violation { tick, machine: Some(*machine), system, problem: format!("inputs changed by {input_delta:?}, expected {inputs:?}") });
+            }
+            if output_delta != outputs {
+                violations.push(Violation { tick, machine: Some(*machine), system, problem: format!("outputs changed by {output_delta:?}, expected {outputs:?}") });
+            }
+        }
+        violations.extend(totals(world, conservation, &after).into_iter().map(|(machine, problem)| Violation { tick, machine, system, problem }));
+
+        for violation in &violations {
+            error!("Items not conserved: {violation}");
+        }
+        let deliveries = world.resource::<Deliveries>().clone();
+        let inventory = world.resource::<Inventory>().clone();
+        let mut conservation = world.resource_mut::<Conservation>();
+        conservation.machines = after;
+        conservation.deliveries = deliveries;
+        conservation.inventory = inventory;
+        conservation.violations.extend(violations);
+    }
+
+    /// Items of each type that left `machines`' input buffers since the last check
+    fn removed(&self, after: &EntityHashMap<Holdings>, machines: impl Fn(&Holdings) -> bool) -> HashMap<ItemType, i64> {
+        let mut removed: HashMap<ItemType, i64> = HashMap::default();
+        for (machine, now) in after.iter().filter(|(_, now)| machines(now)) {
+            let Some(before) = self.machines.get(machine) else { continue };
+            for (item_type, change) in delta(&before.inputs, &now.inputs) {
+                *removed.entry(item_type).or_default() -= change;
+            }
+        }
+        removed
+    }
+
+    /// Checks each machine `consumes` picks only took items out of its inputs, as many as its stats say it used up
+    fn consumers(&self, after: &EntityHashMap<Holdings>, consumes: impl Fn(&Holdings) -> bool) -> Problems {
+        let mut problems = Vec::new();
+        for (machine, now) in after.iter().filter(|(_, now)| consumes(now)) {
+            let Some(before) = self.machines.get(machine) else { continue };
+            let input_delta = delta(&before.inputs, &now.inputs);
+            let taken = now.stats.items_consumed as i64 - before.stats.items_consumed as i64;
+
+            if input_delta.values().any(|change| *change > 0) || !delta(&before.outputs, &now.outputs).is_empty() {
+                problems.push((Some(*machine), format!("items appeared: inputs changed by {input_delta:?}")));
+            } else if input_delta.values().sum::<i64>() != -taken {
+                problems.push((Some(*machine), format!("inputs changed by {input_delta:?} but {taken} were used up")));
+            }
+        }
+        problems
+    }
+}
+
+/// While items are consumed, machines other than the consumers keep their buffers as they are.
+/// Custom machines' behaviour systems run alongside in no particular order, so those aren't checked
+fn unless_consumer(now: &Holdings, consumes: impl Fn(&Holdings) -> bool) -> Option<Expected> {
+    (!matches!(now.kind, MachineKind::Custom(_)) && !consumes(now)).then(Expected::default)
+}
+
+fn is_sink(holdings: &Holdings) -> bool {
+    holdings.kind == MachineKind::Sink
+}
+
+fn is_depositing_storage(holdings: &Holdings) -> bool {
+    holdings.kind == MachineKind::Storage && holdings.deposit
+}
+
+fn is_lab(holdings: &Holdings) -> bool {
+    holdings.kind == MachineKind::Lab
+}
+
+/// Remembers every machine's buffers before the tick's crafting starts, UI actions between ticks move items too
+pub fn start_ledger(world: &mut World) {
+    let machines = Conservation::snapshot(world);
+    let deliveries = world.resource::<Deliveries>().clone();
+    let inventory = world.resource::<Inventory>().clone();
+    let mut conservation = world.resource_mut::<Conservation>();
+    conservation.machines = machines;
+    conservation.deliveries = deliveries;
+    conservation.inventory = inventory;
+    // Already in the snapshot
+    conservation.overflow.clear();
+}
+
+/// Machines starting a craft take its inputs
+pub fn check_ready_craft(world: &mut World) {
+    Conservation::check(world, "ready_craft", |before, now| {
+        let started = match now.status {
+            MachineStatus::Working(working) if before.status != now.status => working.amount as i64,
+            _ => 0,
+        };
+        Some((ledger(&now.recipe.inputs, -started), HashMap::default()))
+    }, |_, _, _| Problems::new());
+}
+
+/// Machines finishing a craft put out its outputs
+pub fn check_craft(world: &mut World) {
+    Conservation::check(world, "craft", |before, now| {
+        let finished = now.stats.crafts as i64 - before.stats.crafts as i64;
+        Some((HashMap::default(), ledger(&now.recipe.outputs, finished)))
+    }, |_, _, _| Problems::new());
+}
+
+/// Items only move from output buffers to input buffers, none get lost or made on the way
+pub fn check_push_outputs(world: &mut World) {
+    Conservation::check(world, "push_outputs", |_, _| None, |_, conservation, after| {
+        let mut problems = Vec::new();
+        let mut moved: HashMap<ItemType, i64> = HashMap::default();
+        for (machine, now) in after.iter() {
+            let Some(before) = conservation.machines.get(machine) else { continue };
+            for (item_type, change) in delta(&before.inputs, &now.inputs) {
+                if change < 0 { problems.push((Some(*machine), format!("{} {item_type:?} left its inputs", -change))); }
+                *moved.entry(item_type).or_default() += change;
+            }
+            for (item_type, change) in delta(&before.outputs, &now.outputs) {
+                if change > 0 { problems.push((Some(*machine), format!("{change} {item_type:?} appeared in its outputs"))); }
+                *moved.entry(item_type).or_default() += change;
+            }
+        }
+        problems.extend(moved.into_iter().filter(|(_, change)| *change != 0).map(|(item_type, change)| (None, format!("{change} {item_type:?} appeared while pushing"))));
+        problems
+    });
+}
+
+/// Whatever leaves a sink is delivered
+pub fn check_deliver_to_sinks(world: &mut World) {
+    Conservation::check(world, "deliver_to_sinks", |_, now| unless_consumer(now, is_sink), |world, conservation, after| {
+        let deliveries = world.resource::<Deliveries>();
+        let mut problems = conservation.consumers(after, is_sink);
+        problems.extend(conservation.removed(after, is_sink).into_iter()
+            .map(|(item_type, removed)| (item_type, removed, deliveries.count(item_type) as i64 - conservation.deliveries.count(item_type) as i64))
+            .filter(|(_, removed, delivered)| removed != delivered)
+            .map(|(item_type, removed, delivered)| (None, format!("{removed} {item_type:?} left sinks but {delivered} were delivered"))));
+        problems
+    });
+}
+
+/// Whatever leaves a depositing storage goes into the Inventory
+pub fn check_deposit_from_storage(world: &mut World) {
+    Conservation::check(world, "deposit_from_storage", |_, now| unless_consumer(now, is_depositing_storage), |world, conservation, after| {
+        let inventory = world.resource::<Inventory>();
+        conservation.removed(after, is_depositing_storage).into_iter()
+            .map(|(item_type, removed)| (item_type, removed, inventory.count(item_type) as i64 - conservation.inventory.count(item_type) as i64))
+            .filter(|(_, removed, deposited)| removed != deposited)
+            .map(|(item_type, removed, deposited)| (None, format!("{removed} {item_type:?} left storages but {deposited} were deposited")))
+            .collect()
+    });
+}
+
+/// Whatever leaves a lab goes towards research
+pub fn check_research_in_labs(world: &mut World) {
+    Conservation::check(world, "research_in_labs", |_, now| unless_consumer(now, is_lab), |_, conservation, after| conservation.consumers(after, is_lab));
+}
@@ -0,0 +1,459 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{build::{build_machine, BuildError}, check_coupling, coupling_allowed, deconstruct_machine, grid::{Grid, GridPos, PlacementError, Rotation}, inventory::{Deposit, Inventory}, pipeline::{circuit::{EnableCondition, Sensor, SensorOf, Sensors}, machine::{BufferSlot, BuildCost, Capacity, Disabled, InputBank, InputPort, ItemBuffer, MachineBindError, MachineId, MachineInput, MachineKind, MachineOutput, MachineStats, MachineStatus, Mult, OutputBank, OutputPort}, recipe::Recipe}, move_machine, set_recipe, spawn_machine, ItemType};
+
+/// Edits further back than this are forgotten
+const MAX_HISTORY: usize = 100;
+
+pub const UNDO_KEY: KeyCode = KeyCode::KeyZ;
+pub const REDO_KEY: KeyCode = KeyCode::KeyY;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditError {
+    /// The machine was removed since the edit was made
+    MachineMissing,
+    /// Not enough of this item in the Inventory, e.g. for undoing would take back items that have been used up since
+    MissingItems(ItemType),
+    Placement(PlacementError),
+    Coupling(MachineBindError),
+    NotCoupled,
+}
+
+impl From<BuildError> for EditError {
+    fn from(value: BuildError) -> Self {
+        match value {
+            BuildError::Placement(err) => EditError::Placement(err),
+            BuildError::MissingItem(item_type) => EditError::MissingItems(item_type),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// A coupling from the `output`th OutputConnector of `src` to the `input`th InputConnector of `dest`.
+/// Refers to machines by MachineId so it still holds after they're despawned and restored
+pub struct CouplingRef {
+    pub src: MachineId,
+    pub output: usize,
+    pub dest: MachineId,
+    pub input: usize,
+}
+
+impl CouplingRef {
+    /// The coupling `output` is part of, if it's coupled
+    pub fn of(world: &World, output: Entity) -> Option<Self> {
+        let MachineOutput(src) = world.get::<MachineOutput>(output)?;
+        let OutputPort(input) = world.get::<OutputPort>(output)?;
+        let MachineInput(dest) = world.get::<MachineInput>(*input)?;
+
+        Some(Self {
+            src: *world.get::<MachineId>(*src)?,
+            output: world.get::<OutputBank>(*src)?.iter().position(|connector| connector == output)?,
+            dest: *world.get::<MachineId>(*dest)?,
+            input: world.get::<InputBank>(*dest)?.iter().position(|connector| connector == *input)?,
+        })
+    }
+
+    /// The OutputConnector and InputConnector this refers to
+    fn connectors(&self, world: &mut World) -> Option<(Entity, Entity)> {
+        let src = find_machine(world, self.src)?;
+        let dest = find_machine(world, self.dest)?;
+
+        Some((*world.get::<OutputBank>(src)?.get().get(self.output)?, *world.get::<InputBank>(dest)?.get().get(self.input)?))
+    }
+
+    fn connect(&self, world: &mut World) -> Result<(), EditError> {
+        let (output, input) = self.connectors(world).ok_or(EditError::MachineMissing)?;
+        check_coupling(world, output, input).map_err(EditError::Coupling)?;
+        world.entity_mut(output).insert(OutputPort(input));
+
+        Ok(())
+    }
+
+    fn disconnect(&self, world: &mut World) -> Result<(), EditError> {
+        let (output, input) = self.connectors(world).ok_or(EditError::MachineMissing)?;
+        if world.get::<OutputPort>(output).is_none_or(|OutputPort(dest)| *dest != input) {
+            return Err(EditError::NotCoupled);
+        }
+        world.entity_mut(output).remove::<OutputPort>();
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Everything needed to put a machine back exactly as it was, down to the items in its buffers
+pub struct MachineSnapshot {
+    pub id: MachineId,
+    pub recipe: Recipe,
+    pub cell: IVec2,
+    pub rotation: Rotation,
+    pub status: MachineStatus,
+    pub stats: MachineStats,
+    pub mult: Option<Mult>,
+    pub build_cost: Option<BuildCost>,
+    /// Buffers and Capacities of the input connectors, by slot
+    pub inputs: Vec<(ItemBuffer, Option<Capacity>)>,
+    pub outputs: Vec<(ItemBuffer, Option<Capacity>)>,
+    pub disabled: bool,
+    pub deposit: bool,
+    pub condition: Option<EnableCondition>,
+    pub sensors: Vec<Sensor>,
+    /// Couplings on both sides of the machine
+    pub couplings: Vec<CouplingRef>,
+}
+
+impl MachineSnapshot {
+    pub fn capture(world: &World, machine: Entity) -> Option<Self> {
+        let entity = world.get_entity(machine).ok()?;
+        Some(Self {
+            id: *entity.get::<MachineId>()?,
+            recipe: *entity.get::<Recipe>()?,
+            cell: entity.get::<GridPos>()?.0,
+            rotation: *entity.get::<Rotation>()?,
+            status: *entity.get::<MachineStatus>()?,
+            stats: *entity.get::<MachineStats>()?,
+            mult: entity.get::<Mult>().copied(),
+            build_cost: entity.get::<BuildCost>().cloned(),
+            inputs: connector_snapshots(world, entity.get::<InputBank>().map(|bank| bank.get().as_slice())),
+            outputs: connector_snapshots(world, entity.get::<OutputBank>().map(|bank| bank.get().as_slice())),
+            disabled: entity.contains::<Disabled>(),
+            deposit: entity.contains::<Deposit>(),
+            condition: entity.get::<EnableCondition>().copied(),
+            sensors: entity.get::<Sensors>().map(|sensors| sensors.iter().filter_map(|sensor| world.get::<Sensor>(sensor)).copied().collect()).unwrap_or_default(),
+            couplings: machine_couplings(world, machine),
+        })
+    }
+
+    /// Spawns the machine again and couples it back to whichever of its neighbours are still around
+    pub fn restore(&self, world: &mut World) -> Result<Entity, PlacementError> {
+        let machine = world.resource_scope(|world, mut grid: Mut<Grid>| {
+            spawn_machine(&mut world.commands(), &mut grid, &format!("{:?}", self.recipe.machine_kind), self.recipe, self.cell, self.rotation)
+        })?;
+        world.flush();
+
+        self.id.reserve();
+        let mut entity = world.entity_mut(machine);
+        entity.insert((self.id, self.status, self.stats));
+        if let Some(mult) = self.mult {
+            entity.insert(mult);
+        }
+        if let Some(build_cost) = &self.build_cost {
+            entity.insert(build_cost.clone());
+        }
+        let connectors: Vec<Entity> = world.get::<InputBank>(machine).map(|bank| bank.get().clone()).unwrap_or_default();
+        restore_connectors(world, &connectors, &self.inputs);
+        let connectors: Vec<Entity> = world.get::<OutputBank>(machine).map(|bank| bank.get().clone()).unwrap_or_default();
+        restore_connectors(world, &connectors, &self.outputs);
+
+        let mut entity = world.entity_mut(machine);
+        if self.disabled {
+            entity.insert(Disabled);
+        }
+        if self.deposit {
+            entity.insert(Deposit);
+        }
+        if let Some(condition) = self.condition {
+            entity.insert(condition);
+        }
+        for sensor in &self.sensors {
+            world.spawn((*sensor, SensorOf(machine)));
+        }
+        reconnect(world, &self.couplings);
+
+        Ok(machine)
+    }
+}
+
+/// Couplings on both sides of `machine`
+fn machine_couplings(world: &World, machine: Entity) -> Vec<CouplingRef> {
+    let upstream: Vec<Entity> = world.get::<InputBank>(machine).map(|bank| bank.iter().filter_map(|input| world.get::<InputPort>(input)).map(InputPort::get).collect()).unwrap_or_default();
+    let downstream: Vec<Entity> = world.get::<OutputBank>(machine).map(|bank| bank.iter().collect()).unwrap_or_default();
+    upstream.into_iter().chain(downstream).filter_map(|output| CouplingRef::of(world, output)).collect()
+}
+
+/// Couples `couplings` back where both machines are still around
+fn reconnect(world: &mut World, couplings: &[CouplingRef]) {
+    for coupling in couplings {
+        match coupling.connect(world) {
+            Ok(()) | Err(EditError::MachineMissing) => {},
+            Err(err) => warn!("Can't restore coupling {coupling:?}: {err:?}"),
+        }
+    }
+}
+
+/// Moves `machine` with move_machine, then removes the couplings CouplingRules don't allow from where it is now.
+/// Gives back the couplings it removed
+fn relocate(world: &mut World, machine: Entity, cell: IVec2, rotation: Rotation) -> Result<Vec<CouplingRef>, EditError> {
+    move_machine(world, machine, cell, rotation).map_err(EditError::Placement)?;
+
+    let mut decoupled = Vec::new();
+    for coupling in machine_couplings(world, machine) {
+        let Some((output, input)) = coupling.connectors(world) else { continue };
+        let (Some(MachineOutput(src)), Some(MachineInput(dest))) = (world.get::<MachineOutput>(output), world.get::<MachineInput>(input)) else { continue };
+        if coupling_allowed(world, *src, *dest).is_ok() { continue; }
+        world.entity_mut(output).remove::<OutputPort>();
+        decoupled.push(coupling);
+    }
+    Ok(decoupled)
+}
+
+/// Buffers and Capacities of `connectors`, by slot
+fn connector_snapshots(world: &World, connectors: Option<&[Entity]>) -> Vec<(ItemBuffer, Option<Capacity>)> {
+    let mut snapshots: Vec<(usize, ItemBuffer, Option<Capacity>)> = connectors.unwrap_or_default().iter()
+        .filter_map(|connector| Some((world.get::<BufferSlot>(*connector)?.0, *world.get::<ItemBuffer>(*connector)?, world.get::<Capacity>(*connector).copied())))
+        .collect();
+    snapshots.sort_by_key(|(slot, ..)| *slot);
+    snapshots.into_iter().map(|(_, buffer, capacity)| (buffer, capacity)).collect()
+}
+
+fn restore_connectors(world: &mut World, connectors: &[Entity], snapshots: &[(ItemBuffer, Option<Capacity>)]) {
+    for connector in connectors {
+        let Some(BufferSlot(slot)) = world.get::<BufferSlot>(*connector).copied() else { continue };
+        let Some((buffer, capacity)) = snapshots.get(slot) else { continue };
+        let mut connector = world.entity_mut(*connector);
+        connector.insert(*buffer);
+        if let Some(capacity) = capacity {
+            connector.insert(*capacity);
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Edit {
+    /// A machine going from one state to another, None meaning it doesn't exist.
+    /// `inventory` is how much of each item the change put into the Inventory, negative for what it took out
+    Machine {
+        id: MachineId,
+        before: Option<Box<MachineSnapshot>>,
+        after: Option<Box<MachineSnapshot>>,
+        inventory: Vec<(ItemType, i64)>,
+    },
+    /// A machine moved from one cell and rotation to another, staying the same entity with the same contents.
+    /// `decoupled` are the couplings the move broke, coupled back when it's undone
+    Move {
+        id: MachineId,
+        from: (IVec2, Rotation),
+        to: (IVec2, Rotation),
+        decoupled: Vec<CouplingRef>,
+    },
+    /// A machine switched from one recipe to another with set_recipe, which puts the items that don't fit in the Inventory
+    /// either way. `couplings` are the ones it had before, which the switch removed
+    Recipe {
+        id: MachineId,
+        from: Box<Recipe>,
+        to: Box<Recipe>,
+        couplings: Vec<CouplingRef>,
+    },
+    Couple(CouplingRef),
+    Decouple(CouplingRef),
+}
+
+impl Edit {
+    /// Reverts the edit if `undo`, otherwise makes it again. Leaves the world untouched when it fails
+    pub fn apply(&self, world: &mut World, undo: bool) -> Result<(), EditError> {
+        match (self, undo) {
+            (Edit::Couple(coupling), false) | (Edit::Decouple(coupling), true) => coupling.connect(world),
+            (Edit::Couple(coupling), true) | (Edit::Decouple(coupling), false) => coupling.disconnect(world),
+            (Edit::Move { id, from, to, decoupled }, _) => {
+                let machine = find_machine(world, *id).ok_or(EditError::MachineMissing)?;
+                let (cell, rotation) = if undo { *from } else { *to };
+                relocate(world, machine, cell, rotation)?;
+                if undo {
+                    reconnect(world, decoupled);
+                }
+
+                Ok(())
+            },
+            (Edit::Recipe { id, from, to, couplings }, _) => {
+                let machine = find_machine(world, *id).ok_or(EditError::MachineMissing)?;
+                set_recipe(&mut world.commands(), machine, if undo { **from } else { **to });
+                world.flush();
+                if undo {
+                    reconnect(world, couplings);
+                }
+
+                Ok(())
+            },
+            (Edit::Machine { id, before, after, inventory }, _) => {
+                let (target, sign) = if undo { (before, -1) } else { (after, 1) };
+                let changes: Vec<(ItemType, i64)> = inventory.iter().map(|(item_type, change)| (*item_type, change * sign)).collect();
+
+                let stock = world.resource::<Inventory>();
+                if let Some((item_type, _)) = changes.iter().find(|(item_type, change)| *change < 0 && stock.count(*item_type) < change.unsigned_abs()) {
+                    return Err(EditError::MissingItems(*item_type));
+                }
+
+                let current = find_machine(world, *id);
+                let current_snapshot = current.and_then(|machine| MachineSnapshot::capture(world, machine));
+                if let Some(machine) = current {
+                    remove_machine(world, machine);
+                }
+                if let Some(target) = target && let Err(err) = target.restore(world) {
+                    if let Some(current) = current_snapshot {
+                        let _ = current.restore(world);
+                    }
+                    return Err(EditError::Placement(err));
+                }
+
+                let mut stock = world.resource_mut::<Inventory>();
+                for (item_type, change) in changes {
+                    if change > 0 {
+                        stock.add(item_type, change as u64);
+                    } else {
+                        stock.take(item_type, change.unsigned_abs());
+                    }
+                }
+
+                Ok(())
+            },
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct EditHistory {
+    /// Oldest first, so the oldest can be forgotten cheaply
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl EditHistory {
+    /// Records a new edit, which makes anything undone so far impossible to redo
+    pub fn push(&mut self, edit: Edit) {
+        self.undo.push_back(edit);
+        if self.undo.len() > MAX_HISTORY {
+            self.undo.pop_front();
+        }
+        self.redo.clear();
+    }
+}
+
+/// Ctrl+Z undoes the last edit, Ctrl+Y or Ctrl+Shift+Z redoes it
+pub fn undo_redo(world: &mut World) {
+    let keys = world.resource::<ButtonInput<KeyCode>>();
+    if !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) { return; }
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let undo = keys.just_pressed(UNDO_KEY) && !shift;
+    let redo = keys.just_pressed(REDO_KEY) || (keys.just_pressed(UNDO_KEY) && shift);
+    if !undo && !redo { return; }
+
+    step_history(world, undo);
+}
+
+/// Undoes the last edit if `undo`, otherwise redoes the last one undone. An edit that fails stays where it was
+pub fn step_history(world: &mut World, undo: bool) {
+    let mut history = world.resource_mut::<EditHistory>();
+    let Some(edit) = (if undo { history.undo.pop_back() } else { history.redo.pop() }) else { return };
+
+    let result = edit.apply(world, undo);
+    let mut history = world.resource_mut::<EditHistory>();
+    match result {
+        Ok(()) if undo => history.redo.push(edit),
+        Ok(()) => history.undo.push_back(edit),
+        Err(err) => {
+            warn!("Can't {} {edit:?}: {err:?}", if undo { "undo" } else { "redo" });
+            if undo { history.undo.push_back(edit) } else { history.redo.push(edit) }
+        },
+    }
+}
+
+/// Places a machine, as an edit that can be undone
+pub fn record_spawn(commands: &mut Commands, recipe: Recipe, cell: IVec2, rotation: Rotation) {
+    record_machine_edit(commands, None, move |world| {
+        build_machine(world, recipe, cell, rotation).map(Some).map_err(EditError::from)
+    });
+}
+
+/// Deconstructs `machine`, as an edit that can be undone
+pub fn record_deconstruct(commands: &mut Commands, machine: Entity) {
+    record_machine_edit(commands, Some(machine), move |world| {
+        deconstruct_machine(&mut world.commands(), machine);
+        Ok(None)
+    });
+}
+
+/// Switches `machine` over to `recipe`, as an edit that can be undone
+pub fn record_set_recipe(commands: &mut Commands, machine: Entity, recipe: Recipe) {
+    commands.queue(move |world: &mut World| {
+        let (Some(id), Some(from)) = (world.get::<MachineId>(machine).copied(), world.get::<Recipe>(machine).copied()) else { return };
+        let couplings = machine_couplings(world, machine);
+        set_recipe(&mut world.commands(), machine, recipe);
+        world.flush();
+
+        world.resource_mut::<EditHistory>().push(Edit::Recipe { id, from: Box::new(from), to: Box::new(recipe), couplings });
+    });
+}
+
+/// Moves `machine` to `cell` facing `rotation`, keeping its contents and the couplings it can still have there,
+/// as an edit that can be undone
+pub fn record_move(commands: &mut Commands, machine: Entity, cell: IVec2, rotation: Rotation) {
+    commands.queue(move |world: &mut World| {
+        let (Some(id), Some(GridPos(from)), Some(from_rotation)) = (world.get::<MachineId>(machine).copied(), world.get::<GridPos>(machine).copied(), world.get::<Rotation>(machine).copied()) else { return };
+        match relocate(world, machine, cell, rotation) {
+            Ok(decoupled) => world.resource_mut::<EditHistory>().push(Edit::Move { id, from: (from, from_rotation), to: (cell, rotation), decoupled }),
+            Err(err) => warn!("Edit failed: {err:?}"),
+        }
+    });
+}
+
+/// Couples `output` to `input`, as an edit that can be undone
+pub fn record_couple(commands: &mut Commands, output: Entity, input: Entity) {
+    commands.queue(move |world: &mut World| {
+        if let Err(err) = check_coupling(world, output, input) {
+            warn!("Can't couple {output} to {input}: {err:?}");
+            return;
+        }
+        world.entity_mut(output).insert(OutputPort(input));
+
+        if let Some(coupling) = CouplingRef::of(world, output) {
+            world.resource_mut::<EditHistory>().push(Edit::Couple(coupling));
+        }
+    });
+}
+
+/// Removes the coupling from `output`, as an edit that can be undone
+pub fn record_decouple(commands: &mut Commands, output: Entity) {
+    commands.queue(move |world: &mut World| {
+        let Some(coupling) = CouplingRef::of(world, output) else { return };
+        world.entity_mut(output).remove::<OutputPort>();
+        world.resource_mut::<EditHistory>().push(Edit::Decouple(coupling));
+    });
+}
+
+/// Runs `change` and records how it changed `machine`, or the machine it spawned.
+/// `change` gives back the machine afterwards, None if it's gone
+fn record_machine_edit(commands: &mut Commands, machine: Option<Entity>, change: impl FnOnce(&mut World) -> Result<Option<Entity>, EditError> + Send + 'static) {
+    commands.queue(move |world: &mut World| {
+        let before = machine.and_then(|machine| MachineSnapshot::capture(world, machine));
+        if machine.is_some() && before.is_none() { return; }
+        let stock = world.resource::<Inventory>().clone();
+
+        let changed = match change(world) {
+            Ok(changed) => changed,
+            Err(err) => {
+                warn!("Edit failed: {err:?}");
+                return;
+            },
+        };
+        world.flush();
+
+        let after = changed.and_then(|machine| MachineSnapshot::capture(world, machine));
+        let Some(id) = before.as_ref().or(after.as_ref()).map(|snapshot| snapshot.id) else { return };
+        let inventory = world.resource::<Inventory>().diff(&stock);
+
+        world.resource_mut::<EditHistory>().push(Edit::Machine { id, before: before.map(Box::new), after: after.map(Box::new), inventory });
+    });
+}
+
+fn find_machine(world: &mut World, id: MachineId) -> Option<Entity> {
+    world.query_filtered::<(Entity, &MachineId), With<MachineKind>>().iter(world).find(|(_, machine_id)| **machine_id == id).map(|(entity, _)| entity)
+}
+
+/// Despawns `machine` without refunding anything, for edits that put it back or replace it
+pub(crate) fn remove_machine(world: &mut World, machine: Entity) {
+    world.resource_mut::<Grid>().vacate(machine);
+    world.entity_mut(machine).despawn();
+}
@@ -0,0 +1,160 @@
+use bevy::{platform::collections::HashMap, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::pipeline::machine::MachineKind;
+
+pub const CELL_SIZE: f32 = 50.0;
+/// Distance between neighbouring ports on the same side of a machine
+const PORT_SPACING: f32 = 20.0;
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// The lowest corner cell a machine occupies
+pub struct GridPos(pub IVec2);
+
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// The side of the machine its outputs sit on, inputs sit on the opposite side
+pub enum Rotation {
+    #[default]
+    East,
+    South,
+    West,
+    North,
+}
+
+impl Rotation {
+    pub fn next(&self) -> Self {
+        match self {
+            Rotation::East => Rotation::South,
+            Rotation::South => Rotation::West,
+            Rotation::West => Rotation::North,
+            Rotation::North => Rotation::East,
+        }
+    }
+
+    /// Points out of the output side
+    pub fn facing(&self) -> IVec2 {
+        match self {
+            Rotation::East => IVec2::X,
+            Rotation::South => IVec2::NEG_Y,
+            Rotation::West => IVec2::NEG_X,
+            Rotation::North => IVec2::Y,
+        }
+    }
+
+    /// Turns an unrotated (east facing) footprint to match this rotation
+    pub fn rotate_footprint(&self, footprint: UVec2) -> UVec2 {
+        match self {
+            Rotation::East | Rotation::West => footprint,
+            Rotation::South | Rotation::North => footprint.yx(),
+        }
+    }
+}
+
+impl MachineKind {
+    /// Cells taken up when facing east
+    pub fn footprint(&self) -> UVec2 {
+        match self {
+            MachineKind::Producer | MachineKind::Transformer => UVec2::new(4, 3),
+            MachineKind::Combinator | MachineKind::Separator | MachineKind::Storage => UVec2::new(4, 4),
+            MachineKind::Sink | MachineKind::Lab => UVec2::new(2, 2),
+            MachineKind::Custom(machine) => machine.footprint,
+        }
+    }
+}
+
+/// World space size of a machine of `kind` placed with `rotation`
+pub fn machine_size(kind: MachineKind, rotation: Rotation) -> Vec2 {
+    rotation.rotate_footprint(kind.footprint()).as_vec2() * CELL_SIZE
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlacementError {
+    /// Another machine is already in the way
+    Occupied(Entity),
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+/// Which machine sits in each occupied cell
+pub struct Grid {
+    cells: HashMap<IVec2, Entity>,
+}
+
+impl Grid {
+    pub fn snap(position: Vec2) -> IVec2 {
+        (position / CELL_SIZE).floor().as_ivec2()
+    }
+
+    /// Snaps a footprint centered on `position` to the grid, giving the cell it would start at
+    pub fn snap_footprint(position: Vec2, footprint: UVec2) -> IVec2 {
+        Self::snap(position - footprint.as_vec2() * CELL_SIZE * 0.5 + CELL_SIZE * 0.5)
+    }
+
+    /// World space area covered by a footprint starting at `origin`
+    pub fn rect(origin: IVec2, footprint: UVec2) -> Rect {
+        let min = origin.as_vec2() * CELL_SIZE;
+        Rect::from_corners(min, min + footprint.as_vec2() * CELL_SIZE)
+    }
+
+    pub fn cells(origin: IVec2, footprint: UVec2) -> impl Iterator<Item = IVec2> {
+        (0..footprint.x as i32).flat_map(move |x| (0..footprint.y as i32).map(move |y| origin + IVec2::new(x, y)))
+    }
+
+    pub fn occupant(&self, cell: IVec2) -> Option<Entity> {
+        self.cells.get(&cell).copied()
+    }
+
+    pub fn check(&self, origin: IVec2, footprint: UVec2) -> Result<(), PlacementError> {
+        self.check_except(origin, footprint, Entity::PLACEHOLDER)
+    }
+
+    /// Like check, but `except` isn't in the way, so a machine can be moved onto cells it already covers
+    pub fn check_except(&self, origin: IVec2, footprint: UVec2, except: Entity) -> Result<(), PlacementError> {
+        match Self::cells(origin, footprint).find_map(|cell| self.occupant(cell).filter(|occupant| *occupant != except)) {
+            Some(occupant) => Err(PlacementError::Occupied(occupant)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn occupy(&mut self, entity: Entity, origin: IVec2, footprint: UVec2) -> Result<(), PlacementError> {
+        self.check(origin, footprint)?;
+        self.cells.extend(Self::cells(origin, footprint).map(|cell| (cell, entity)));
+
+        Ok(())
+    }
+
+    pub fn vacate(&mut self, entity: Entity) {
+        self.cells.retain(|_, occupant| *occupant != entity);
+    }
+
+    /// Cells just outside the side of a footprint that `rotation` faces
+    pub fn facing_cells(origin: IVec2, footprint: UVec2, rotation: Rotation) -> impl Iterator<Item = IVec2> {
+        let facing = rotation.facing();
+        Self::cells(origin, footprint).map(move |cell| cell + facing).filter(move |cell| !Self::cells(origin, footprint).any(|c| c == *cell))
+    }
+
+    /// `src` and `dest` are right next to each other, with the output side of `src` touching the input side of `dest`
+    pub fn ports_face(&self, src: Entity, src_placement: (GridPos, Rotation, UVec2), dest: Entity, dest_placement: (GridPos, Rotation, UVec2)) -> bool {
+        let (GridPos(src_origin), src_rotation, src_footprint) = src_placement;
+        let (GridPos(dest_origin), dest_rotation, dest_footprint) = dest_placement;
+
+        src_rotation == dest_rotation
+            && Self::facing_cells(src_origin, src_footprint, src_rotation).any(|cell| self.occupant(cell) == Some(dest))
+            && Self::facing_cells(dest_origin, dest_footprint, dest_rotation.next().next()).any(|cell| self.occupant(cell) == Some(src))
+    }
+}
+
+/// Where the `index`th of `count` ports on a machine of `size` sits, relative to its center.
+/// Outputs go on the side the machine faces, inputs on the opposite one
+pub fn port_offset(size: Vec2, rotation: Rotation, output: bool, index: usize, count: usize) -> Vec2 {
+    let facing = rotation.facing().as_vec2();
+    let side = if output { facing } else { -facing };
+    let along = (index as f32 - (count as f32 - 1.0) * 0.5) * PORT_SPACING;
+
+    side * size * 0.5 - facing.perp() * along
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct CouplingRules {
+    /// Only allow coupling machines whose ports face each other across a shared edge
+    pub require_adjacent: bool,
+}
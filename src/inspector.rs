@@ -0,0 +1,315 @@
+use bevy::prelude::*;
+
+use crate::{inventory::{fill_inputs, take_outputs, Deposit}, edit::{record_decouple, record_deconstruct, record_set_recipe}, pipeline::{circuit::{add_sensor, sensor_readings, Channel, CircuitReadout, Comparison, EnableCondition, Sensor, Sensors}, machine::{set_enabled, Coupling, CouplingGraph, Disabled, MachineBuffers, MachineKind, MachineStats, MachineStatus, Mult}, recipe::{ItemStack, Recipe, Recipes}, IoBuffer, SimTick}, research::Research, ui::{spawn_button, PANEL_COLOR}};
+
+const PANEL_WIDTH: f32 = 280.0;
+const MAX_MULT: u64 = 16;
+/// Channels the inspector buttons cycle through
+const CHANNELS: u32 = 8;
+const LIMIT_STEP: u64 = 5;
+
+pub const DECONSTRUCT_KEY: KeyCode = KeyCode::Delete;
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+/// The machine shown in the inspector, if any
+pub struct Inspected(pub Option<Entity>);
+
+#[derive(Component, Clone, Debug)]
+pub struct InspectorPanel;
+
+#[derive(Component, Clone, Debug)]
+pub struct InspectorText;
+
+#[derive(Component, Clone, Debug, Default)]
+/// Holds a row of buttons per coupling, along with the couplings they were built for
+pub struct InspectorNeighbours(Vec<(Coupling, Direction)>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Upstream,
+    Downstream,
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+pub enum InspectorAction {
+    PreviousRecipe,
+    NextRecipe,
+    LowerMult,
+    RaiseMult,
+    /// Inspect another machine and move the camera to it
+    Focus(Entity),
+    /// Switch the machine off, or back on
+    ToggleEnabled,
+    /// Move everything in the output buffers into the Inventory
+    TakeOutputs,
+    /// Top up the input buffers from the Inventory
+    FillInputs,
+    /// Have a Sink or Storage put what it receives into the Inventory, or stop doing so
+    ToggleDeposit,
+    /// Remove the coupling from this OutputConnector
+    Decouple(Entity),
+    Deconstruct,
+    Close,
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+/// Buttons for setting up a machine's sensor and enable condition
+pub enum CircuitAction {
+    /// Cycle what the machine's sensor reads, ending with no sensor
+    NextReading,
+    NextSensorChannel,
+    /// Cycle the enable condition's comparison, ending with no condition
+    NextComparison,
+    NextConditionChannel,
+    LowerLimit,
+    RaiseLimit,
+}
+
+type ControlledMachine = (&'static Recipe, Option<&'static Mult>, &'static Transform, Has<Disabled>, Has<Deposit>);
+type InspectedMachine = (&'static MachineKind, &'static Recipe, &'static MachineStatus, &'static MachineStats, Option<&'static Mult>, Option<&'static EnableCondition>, Option<&'static Sensors>, Has<Disabled>, Has<Deposit>);
+
+/// Makes clicking `machine` open it in the inspector
+pub fn inspect_on_click(machine: Entity) -> impl FnMut(On<Pointer<Click>>, ResMut<Inspected>) {
+    move |click, mut inspected| {
+        if click.button == PointerButton::Primary {
+            inspected.0 = Some(machine);
+        }
+    }
+}
+
+pub fn setup_inspector(mut commands: Commands) {
+    commands.spawn((
+        InspectorPanel,
+        Node {
+            display: Display::None,
+            position_type: PositionType::Absolute,
+            flex_direction: FlexDirection::Column,
+            right: px(0),
+            top: px(0),
+            width: px(PANEL_WIDTH),
+            height: percent(100),
+            padding: UiRect::all(px(10)),
+            row_gap: px(10),
+            ..default()
+        },
+        BackgroundColor(PANEL_COLOR),
+        Interaction::default(),
+    )).with_children(|builder| {
+        builder.spawn((InspectorText, Text::new(""), TextFont {
+            font_size: 12.0,
+            ..default()
+        }));
+        builder.spawn(Node {
+            flex_wrap: FlexWrap::Wrap,
+            column_gap: px(5),
+            row_gap: px(5),
+            ..default()
+        }).with_children(|builder| {
+            spawn_button(builder, "< Recipe", InspectorAction::PreviousRecipe);
+            spawn_button(builder, "Recipe >", InspectorAction::NextRecipe);
+            spawn_button(builder, "Mult -", InspectorAction::LowerMult);
+            spawn_button(builder, "Mult +", InspectorAction::RaiseMult);
+            spawn_button(builder, "On/Off", InspectorAction::ToggleEnabled);
+            spawn_button(builder, "Take outputs", InspectorAction::TakeOutputs);
+            spawn_button(builder, "Fill inputs", InspectorAction::FillInputs);
+            spawn_button(builder, "Deposit", InspectorAction::ToggleDeposit);
+            spawn_button(builder, "Deconstruct", InspectorAction::Deconstruct);
+            spawn_button(builder, "Close", InspectorAction::Close);
+        });
+        builder.spawn(Node {
+            flex_wrap: FlexWrap::Wrap,
+            column_gap: px(5),
+            row_gap: px(5),
+            ..default()
+        }).with_children(|builder| {
+            spawn_button(builder, "Sensor", CircuitAction::NextReading);
+            spawn_button(builder, "Sensor ch", CircuitAction::NextSensorChannel);
+            spawn_button(builder, "Condition", CircuitAction::NextComparison);
+            spawn_button(builder, "Condition ch", CircuitAction::NextConditionChannel);
+            spawn_button(builder, "Limit -", CircuitAction::LowerLimit);
+            spawn_button(builder, "Limit +", CircuitAction::RaiseLimit);
+        });
+        builder.spawn((InspectorNeighbours::default(), Node {
+            flex_direction: FlexDirection::Column,
+            row_gap: px(5),
+            ..default()
+        }));
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_inspector(mut commands: Commands, (mut inspected, tick): (ResMut<Inspected>, Res<SimTick>), machine_query: Query<InspectedMachine>, buffers: MachineBuffers, graph: CouplingGraph, circuit: CircuitReadout, mut panel_query: Query<&mut Node, With<InspectorPanel>>, mut text_query: Query<&mut Text, With<InspectorText>>, mut neighbours_query: Query<(Entity, &mut InspectorNeighbours)>) {
+    let Ok(mut panel) = panel_query.single_mut() else { return };
+    let Some(machine) = inspected.0 else {
+        panel.display = Display::None;
+        return;
+    };
+    // Despawned while inspected
+    let Ok((kind, recipe, status, stats, mult, condition, sensors, disabled, deposit)) = machine_query.get(machine) else {
+        inspected.0 = None;
+        return;
+    };
+    panel.display = Display::Flex;
+
+    if let Ok(mut text) = text_query.single_mut() {
+        let mut info = format!("{kind:?}\n\nRecipe - {} ticks\nIn: {}\nOut: {}", recipe.ticks, format_stacks(&recipe.inputs), format_stacks(&recipe.outputs));
+        info = match status {
+            MachineStatus::Working(working) => format!("{info}\n\nStatus: {} ({:.0}%)", status.describe(disabled, tick.0), working.progress(recipe, tick.0) * 100.0),
+            _ => format!("{info}\n\nStatus: {}", status.describe(disabled, tick.0)),
+        };
+        info = format!("{info}\nMult: x{}", mult.unwrap_or(&Mult(1)).0);
+        info = format!("{info}{}", circuit.describe(condition, sensors));
+        if deposit {
+            info = format!("{info}\nDepositing into the Inventory");
+        }
+        info = format!("{info}\n\nInput buffers{}", format_buffers(&buffers.inputs(machine)));
+        info = format!("{info}\nOutput buffers{}", format_buffers(&buffers.outputs(machine)));
+        info = format!("{info}\n\nCrafts: {}\nConsumed: {}\nProduced: {}\nWorking {}/{} ticks", stats.crafts, stats.items_consumed, stats.items_produced, stats.ticks_working, stats.ticks_total(tick.0));
+
+        text.0 = info;
+    }
+
+    let Ok((list, mut neighbours)) = neighbours_query.single_mut() else { return };
+    let current: Vec<(Coupling, Direction)> = graph.upstream(machine).into_iter().map(|c| (c, Direction::Upstream))
+        .chain(graph.downstream(machine).into_iter().map(|c| (c, Direction::Downstream)))
+        .collect();
+    if current == neighbours.0 { return; }
+
+    commands.entity(list).despawn_related::<Children>().with_children(|builder| {
+        for (coupling, direction) in &current {
+            let neighbour = match direction {
+                Direction::Upstream => coupling.src,
+                Direction::Downstream => coupling.dest,
+            };
+            let kind = machine_query.get(neighbour).map(|(kind, ..)| format!("{kind:?}")).unwrap_or_default();
+            let label = match direction {
+                Direction::Upstream => format!("<- {kind} {neighbour}"),
+                Direction::Downstream => format!("-> {kind} {neighbour}"),
+            };
+            builder.spawn(Node {
+                column_gap: px(5),
+                ..default()
+            }).with_children(|builder| {
+                spawn_button(builder, label, InspectorAction::Focus(neighbour));
+                spawn_button(builder, "x", InspectorAction::Decouple(coupling.output));
+            });
+        }
+    });
+    neighbours.0 = current;
+}
+
+pub fn handle_inspector_buttons(mut commands: Commands, keys: Res<ButtonInput<KeyCode>>, mut inspected: ResMut<Inspected>, (recipes, research): (Res<Recipes>, Res<Research>), button_query: Query<(&Interaction, &InspectorAction), Changed<Interaction>>, machine_query: Query<ControlledMachine, Without<Camera2d>>, mut camera_query: Query<&mut Transform, With<Camera2d>>) {
+    if keys.just_pressed(KeyCode::Escape) {
+        inspected.0 = None;
+    }
+    if keys.just_pressed(DECONSTRUCT_KEY) && let Some(machine) = inspected.0 {
+        record_deconstruct(&mut commands, machine);
+        inspected.0 = None;
+    }
+
+    for (_, action) in button_query.iter().filter(|(interaction, _)| **interaction == Interaction::Pressed) {
+        let Some(machine) = inspected.0 else { continue };
+        let Ok((recipe, mult, _, disabled, deposit)) = machine_query.get(machine) else { continue };
+
+        match *action {
+            InspectorAction::PreviousRecipe | InspectorAction::NextRecipe => {
+                let options: Vec<&Recipe> = recipes.inner.iter().filter(|r| r.machine_kind == recipe.machine_kind && (research.is_unlocked(r) || *r == recipe)).collect();
+                let Some(current) = options.iter().position(|r| *r == recipe) else { continue };
+                let next = match action {
+                    InspectorAction::NextRecipe => (current + 1) % options.len(),
+                    _ => (current + options.len() - 1) % options.len(),
+                };
+                if next != current {
+                    record_set_recipe(&mut commands, machine, *options[next]);
+                }
+            },
+            InspectorAction::LowerMult | InspectorAction::RaiseMult => {
+                let mult = mult.unwrap_or(&Mult(1)).0;
+                let mult = match action {
+                    InspectorAction::RaiseMult => (mult + 1).min(MAX_MULT),
+                    _ => mult.saturating_sub(1).max(1),
+                };
+                commands.entity(machine).insert(Mult(mult));
+            },
+            InspectorAction::Focus(target) => {
+                let Ok((_, _, target_transform, ..)) = machine_query.get(target) else { continue };
+                if let Ok(mut camera) = camera_query.single_mut() {
+                    camera.translation = target_transform.translation.truncate().extend(camera.translation.z);
+                }
+                inspected.0 = Some(target);
+            },
+            InspectorAction::ToggleEnabled => set_enabled(&mut commands, machine, disabled),
+            InspectorAction::TakeOutputs => take_outputs(&mut commands, machine),
+            InspectorAction::FillInputs => fill_inputs(&mut commands, machine),
+            InspectorAction::ToggleDeposit => {
+                if deposit {
+                    commands.entity(machine).remove::<Deposit>();
+                } else if matches!(recipe.machine_kind, MachineKind::Sink | MachineKind::Storage) {
+                    commands.entity(machine).insert(Deposit);
+                }
+            },
+            InspectorAction::Decouple(output) => record_decouple(&mut commands, output),
+            InspectorAction::Deconstruct => {
+                record_deconstruct(&mut commands, machine);
+                inspected.0 = None;
+            },
+            InspectorAction::Close => inspected.0 = None,
+        }
+    }
+}
+
+pub fn handle_circuit_buttons(mut commands: Commands, inspected: Res<Inspected>, button_query: Query<(&Interaction, &CircuitAction), Changed<Interaction>>, machine_query: Query<(&Recipe, Option<&EnableCondition>, Option<&Sensors>)>, mut sensor_query: Query<&mut Sensor>) {
+    for (_, action) in button_query.iter().filter(|(interaction, _)| **interaction == Interaction::Pressed) {
+        let Some(machine) = inspected.0 else { continue };
+        let Ok((recipe, condition, sensors)) = machine_query.get(machine) else { continue };
+        let sensor = sensors.and_then(|sensors| sensors.iter().next());
+
+        match action {
+            CircuitAction::NextReading => {
+                let readings = sensor_readings(recipe);
+                match sensor.and_then(|sensor| Some((sensor, sensor_query.get_mut(sensor).ok()?))) {
+                    None => if let Some(reading) = readings.first() {
+                        add_sensor(&mut commands, machine, *reading, Channel::default());
+                    },
+                    Some((entity, mut sensor)) => match readings.iter().position(|r| *r == sensor.reads).and_then(|i| readings.get(i + 1)) {
+                        Some(reading) => sensor.reads = *reading,
+                        None => commands.entity(entity).despawn(),
+                    },
+                }
+            },
+            CircuitAction::NextSensorChannel => {
+                if let Some(mut sensor) = sensor.and_then(|sensor| sensor_query.get_mut(sensor).ok()) {
+                    sensor.channel = Channel((sensor.channel.0 + 1) % CHANNELS);
+                }
+            },
+            CircuitAction::NextComparison => {
+                let next = match condition.and_then(|c| Comparison::ALL.iter().position(|comparison| *comparison == c.comparison)) {
+                    None => Some(Comparison::ALL[0]),
+                    Some(i) => Comparison::ALL.get(i + 1).copied(),
+                };
+                match next {
+                    Some(comparison) => { commands.entity(machine).insert(EnableCondition { comparison, ..condition.copied().unwrap_or(EnableCondition { channel: Channel::default(), comparison, value: 0 }) }); },
+                    None => { commands.entity(machine).remove::<EnableCondition>(); },
+                }
+            },
+            CircuitAction::NextConditionChannel | CircuitAction::LowerLimit | CircuitAction::RaiseLimit => {
+                let Some(mut condition) = condition.copied() else { continue };
+                match action {
+                    CircuitAction::NextConditionChannel => condition.channel = Channel((condition.channel.0 + 1) % CHANNELS),
+                    CircuitAction::LowerLimit => condition.value = condition.value.saturating_sub(LIMIT_STEP),
+                    _ => condition.value += LIMIT_STEP,
+                }
+                commands.entity(machine).insert(condition);
+            },
+        }
+    }
+}
+
+fn format_stacks(stacks: &[Option<ItemStack>; 4]) -> String {
+    let stacks: Vec<String> = stacks.iter().filter_map(|s| *s).map(|s| format!("{:?} x{}", s.item_type, s.amount)).collect();
+    if stacks.is_empty() { String::from("-") } else { stacks.join(", ") }
+}
+
+fn format_buffers(buffers: &[IoBuffer]) -> String {
+    buffers.iter().fold(String::new(), |text, buf| format!("{text}\n  {:?} - {}/{}", buf.item_type, buf.buffer.current, buf.buffer.max))
+}
@@ -0,0 +1,135 @@
+use bevy::{platform::collections::HashMap, prelude::*};
+
+use crate::{pipeline::{machine::{BufferType, InputBank, ItemBuffer, MachineKind, OutputBank}, recipe::ItemStack, wake::Wakeups}, ui::PANEL_COLOR, ItemType};
+
+pub const INVENTORY_KEY: KeyCode = KeyCode::KeyI;
+
+#[derive(Resource, Clone, Debug, Default)]
+/// Items held outside of any machine
+pub struct Inventory {
+    counts: HashMap<ItemType, u64>,
+}
+
+impl Inventory {
+    pub fn add(&mut self, item_type: ItemType, amount: u64) {
+        if amount > 0 {
+            *self.counts.entry(item_type).or_default() += amount;
+        }
+    }
+
+    pub fn add_stacks(&mut self, stacks: impl IntoIterator<Item = ItemStack>) {
+        for stack in stacks {
+            self.add(stack.item_type, stack.amount);
+        }
+    }
+
+    /// Every item there's any of, in a stable order
+    pub fn iter(&self) -> impl Iterator<Item = (ItemType, u64)> {
+        let mut counts: Vec<(ItemType, u64)> = self.counts.iter().map(|(item_type, count)| (*item_type, *count)).filter(|(_, count)| *count > 0).collect();
+        counts.sort();
+        counts.into_iter()
+    }
+
+    pub fn count(&self, item_type: ItemType) -> u64 {
+        self.counts.get(&item_type).copied().unwrap_or_default()
+    }
+
+    /// Removes `amount` of `item_type`, or nothing at all if there isn't that much
+    pub fn take(&mut self, item_type: ItemType, amount: u64) -> bool {
+        let count = self.count(item_type);
+        if count < amount { return false; }
+
+        self.counts.insert(item_type, count - amount);
+        true
+    }
+
+    /// How much of each item there is now compared to `earlier`, leaving out unchanged ones
+    pub fn diff(&self, earlier: &Inventory) -> Vec<(ItemType, i64)> {
+        self.counts.keys().chain(earlier.counts.keys())
+            .map(|item_type| (*item_type, self.count(*item_type) as i64 - earlier.count(*item_type) as i64))
+            .filter(|(_, change)| *change != 0)
+            .collect::<HashMap<_, _>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug, Default)]
+/// Makes a Sink or Storage put whatever it receives into the Inventory
+pub struct Deposit;
+
+#[derive(Component, Clone, Debug)]
+pub struct InventoryPanel;
+
+/// Moves everything in `machine`'s output buffers into the Inventory
+pub fn take_outputs(commands: &mut Commands, machine: Entity) {
+    commands.queue(move |world: &mut World| {
+        let Some(outputs) = world.get::<OutputBank>(machine).map(|bank| bank.get().clone()) else { return };
+        let mut taken: Vec<ItemStack> = Vec::new();
+        for output in outputs {
+            let Some(BufferType(item_type)) = world.get::<BufferType>(output).cloned() else { continue };
+            let Some(mut buffer) = world.get_mut::<ItemBuffer>(output) else { continue };
+            taken.push(ItemStack::new(item_type, std::mem::take(&mut buffer.current)));
+        }
+        world.resource_mut::<Inventory>().add_stacks(taken);
+        world.resource_mut::<Wakeups>().wake(machine);
+    });
+}
+
+/// Tops up `machine`'s input buffers with whatever the Inventory has
+pub fn fill_inputs(commands: &mut Commands, machine: Entity) {
+    commands.queue(move |world: &mut World| {
+        world.resource_scope(|world, mut inventory: Mut<Inventory>| {
+            let Some(inputs) = world.get::<InputBank>(machine).map(|bank| bank.get().clone()) else { return };
+            for input in inputs {
+                let Some(BufferType(item_type)) = world.get::<BufferType>(input).cloned() else { continue };
+                let Some(mut buffer) = world.get_mut::<ItemBuffer>(input) else { continue };
+                let moved = buffer.remaining().min(inventory.count(item_type));
+                inventory.take(item_type, moved);
+                buffer.current += moved;
+            }
+        });
+        world.resource_mut::<Wakeups>().wake(machine);
+    });
+}
+
+/// Empties depositing Storages into the Inventory. Depositing Sinks are handled by deliver_to_sinks, so deliveries still count
+pub fn deposit_from_storage(mut inventory: ResMut<Inventory>, storage_query: Query<(&MachineKind, &InputBank), With<Deposit>>, mut buffer_query: Query<(&BufferType, &mut ItemBuffer)>) {
+    for (_, inputs) in storage_query.iter().filter(|(kind, _)| **kind == MachineKind::Storage) {
+        let mut buffers = buffer_query.iter_many_mut(inputs.iter());
+        while let Some((BufferType(item_type), mut buffer)) = buffers.fetch_next() {
+            if buffer.current == 0 { continue; }
+            inventory.add(*item_type, std::mem::take(&mut buffer.current));
+        }
+    }
+}
+
+pub fn setup_inventory_panel(mut commands: Commands) {
+    commands.spawn((
+        InventoryPanel,
+        Text::new(""),
+        TextFont {
+            font_size: 12.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            left: px(10),
+            top: px(120),
+            padding: UiRect::all(px(6)),
+            ..default()
+        },
+        BackgroundColor(PANEL_COLOR),
+    ));
+}
+
+pub fn update_inventory_panel(keys: Res<ButtonInput<KeyCode>>, inventory: Res<Inventory>, mut panel_query: Query<(&mut Text, &mut Node), With<InventoryPanel>>) {
+    let Ok((mut text, mut node)) = panel_query.single_mut() else { return };
+
+    if keys.just_pressed(INVENTORY_KEY) {
+        node.display = if node.display == Display::None { Display::Flex } else { Display::None };
+    }
+    if inventory.is_changed() {
+        text.0 = inventory.iter().fold(String::from("Inventory"), |text, (item_type, count)| format!("{text}\n{item_type:?}: {count}"));
+    }
+}
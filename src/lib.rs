@@ -0,0 +1,403 @@
+use crate::{build::{BuildRules, couple_on_drop, end_drag, start_drag, track_drag}, camera::{frame_all, setup_camera}, grid::{machine_size, port_offset, CouplingRules, Grid, GridPos, PlacementError, Rotation}, inventory::Inventory, inspector::inspect_on_click, modding::CustomItem, objectives::{Objective, Objectives}, pipeline::{circuit::{add_sensor, Channel, Comparison, EnableCondition, SensorReading}, machine::{fit_buffers, input_buffers, output_buffers, BufferSlot, BufferType, BuildCost, Disabled, InputBank, InputBufferText, InputPort, ItemBuffer, MachineBindError, MachineBuffers, MachineInput, MachineKind, MachineOutput, MachineStatus, NameText, OutputBank, OutputBufferText, OutputPort, StatusText}, recipe::{ItemStack, Recipe, Recipes}, SimTick}, plugin::FactoryPlugin, save::resume_game};
+use std::time::Duration;
+
+use bevy::{prelude::*, sprite::Anchor};
+use serde::{Deserialize, Serialize};
+
+pub mod alerts;
+pub mod build;
+pub mod camera;
+pub mod conservation;
+pub mod edit;
+pub mod grid;
+pub mod inspector;
+pub mod inventory;
+pub mod links;
+pub mod modding;
+pub mod objectives;
+pub mod pipeline;
+pub mod plugin;
+pub mod research;
+pub mod save;
+pub mod sim;
+pub mod ui;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ItemType {
+    Producer,
+    Transformer,
+    Combinator,
+    Separator,
+    Storage,
+    Input,
+    Output,
+    /// Added by a mod, see FactoryAppExt::add_item. Saved by name, and looked up in the Registry on load
+    #[serde(with = "modding::custom_item")]
+    Custom(&'static CustomItem),
+}
+
+impl std::fmt::Debug for ItemType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ItemType::Producer => "Producer",
+            ItemType::Transformer => "Transformer",
+            ItemType::Combinator => "Combinator",
+            ItemType::Separator => "Separator",
+            ItemType::Storage => "Storage",
+            ItemType::Input => "Input",
+            ItemType::Output => "Output",
+            ItemType::Custom(item) => item.name,
+        })
+    }
+}
+
+impl ItemType {
+    pub fn color(&self) -> Color {
+        match self {
+            ItemType::Producer => Color::linear_rgb(1.0, 0.3, 0.3),
+            ItemType::Transformer => Color::linear_rgb(1.0, 0.8, 0.2),
+            ItemType::Combinator => Color::linear_rgb(0.7, 0.3, 1.0),
+            ItemType::Separator => Color::linear_rgb(1.0, 0.4, 0.8),
+            ItemType::Storage => Color::linear_rgb(0.6, 0.45, 0.3),
+            ItemType::Input => Color::linear_rgb(0.25, 0.5, 1.0),
+            ItemType::Output => Color::linear_rgb(1.0, 0.5, 0.0),
+            ItemType::Custom(item) => item.color,
+        }
+    }
+}
+
+/// The whole game, ready to run. Mods add their plugins to it before running it
+pub fn app() -> App {
+    let mut app = App::new();
+    app.add_plugins((DefaultPlugins, FactoryPlugin::default()))
+        .insert_resource(BuildRules { consume_items: true })
+        .insert_resource(starting_inventory())
+        .insert_resource(Objectives(vec![Objective::deliver(ItemType::Combinator, 100).within(Duration::from_secs(10 * 60))]))
+        .add_systems(Startup, ((setup, frame_all).chain().after(setup_camera), resume_game.after(setup)));
+
+    app
+}
+
+/// Enough machines to start building a factory in progression mode, the rest have to be crafted
+fn starting_inventory() -> Inventory {
+    let mut inventory = Inventory::default();
+    inventory.add(ItemType::Producer, 4);
+    inventory.add(ItemType::Transformer, 2);
+    inventory.add(ItemType::Combinator, 2);
+    inventory
+}
+
+fn setup(mut commands: Commands, mut grid: ResMut<Grid>, recipes: Res<Recipes>) {
+    let producer1 = spawn_machine(&mut commands, &mut grid, "Producer", recipes.get_producer(ItemType::Input).unwrap(), IVec2::new(0, 0), Rotation::East).unwrap();
+    let producer2 = spawn_machine(&mut commands, &mut grid, "Producer", recipes.get_producer(ItemType::Output).unwrap(), IVec2::new(0, -5), Rotation::East).unwrap();
+    let producer3 = spawn_machine(&mut commands, &mut grid, "Producer", recipes.get_producer(ItemType::Input).unwrap(), IVec2::new(6, -8), Rotation::East).unwrap();
+    let combinator1 = spawn_machine(&mut commands, &mut grid, "Combinator", recipes.get_combinator(ItemType::Transformer).unwrap(), IVec2::new(6, -3), Rotation::East).unwrap();
+    let combinator2 = spawn_machine(&mut commands, &mut grid, "Combinator", recipes.get_combinator(ItemType::Combinator).unwrap(), IVec2::new(12, -5), Rotation::East).unwrap();
+    let sink = spawn_machine(&mut commands, &mut grid, "Sink", recipes.get_sink(ItemType::Combinator).unwrap(), IVec2::new(18, -4), Rotation::East).unwrap();
+
+    bind_output(&mut commands, producer1, combinator1, ItemType::Input);
+    bind_output(&mut commands, producer3, combinator2, ItemType::Input);
+    bind_output(&mut commands, producer2, combinator1, ItemType::Output);
+    bind_output(&mut commands, combinator1, combinator2, ItemType::Transformer);
+    bind_output(&mut commands, combinator2, sink, ItemType::Combinator);
+
+    // Keep combinator1 from making more Transformers than combinator2 can use
+    add_sensor(&mut commands, combinator1, SensorReading::OutputFill(ItemType::Transformer), Channel(0));
+    commands.entity(combinator1).insert(EnableCondition { channel: Channel(0), comparison: Comparison::Less, value: 20 });
+}
+
+pub fn create_label(commands: &mut Commands, name: &str, entity: Entity, size: Vec2) {
+    let font = TextFont {
+        font_size: 12.0,
+        ..default()
+    };
+    let [name_offset, status_offset, input_offset, output_offset] = label_offsets(size);
+
+    commands.entity(entity).insert(Sprite::from_color(Color::BLACK, size)).with_children(|builder| {
+        let name_text = NameText(builder.spawn((Text2d::new(name), font.clone(), Transform::from_translation(name_offset))).id());
+        let status_text = StatusText(builder.spawn((Text2d::new(""), font.clone(), Transform::from_translation(status_offset))).id());
+        let input_buffer_text = InputBufferText(builder.spawn((
+            Text2d::new(""),
+            font.clone(),
+            TextLayout::new_with_justify(Justify::Left),
+            Anchor::TOP_LEFT,
+            Transform::from_translation(input_offset),
+        )).id());
+        let output_buffer_text = OutputBufferText(builder.spawn((
+            Text2d::new(""),
+            font.clone(),
+            TextLayout::new_with_justify(Justify::Right),
+            Anchor::TOP_RIGHT,
+            Transform::from_translation(output_offset),
+        )).id());
+
+        builder.commands().entity(entity).insert((name_text, status_text, input_buffer_text, output_buffer_text));
+    });
+}
+
+/// Where the name, status, input and output labels go on a machine of `size`
+fn label_offsets(size: Vec2) -> [Vec3; 4] {
+    [
+        Vec3::new(0.0, size.y*0.5 - 12.0, 1.0),
+        Vec3::new(0.0, size.y*0.5 - 30.0, 1.0),
+        Vec3::new(-size.x*0.5 + 10.0, size.y*0.5 - 45.0, 1.0),
+        Vec3::new(size.x*0.5 - 10.0, size.y*0.5 - 45.0, 1.0),
+    ]
+}
+
+type LabelledMachine = (Entity, &'static InputBufferText, &'static OutputBufferText, &'static StatusText, Ref<'static, MachineStatus>, Has<Disabled>);
+
+/// Rewrites the labels of machines whose buffers or status changed, and counts down working machines
+pub fn update_labels(tick: Res<SimTick>, machine_query: Query<LabelledMachine>, buffers: MachineBuffers, mut label_query: Query<&mut Text2d>) {
+    for (machine, input_label, output_label, status_label, status, disabled) in machine_query {
+        let (inputs_changed, outputs_changed) = buffers.changed(machine);
+        if inputs_changed {
+            let mut input_label = label_query.get_mut(input_label.0).unwrap();
+            let mut text = String::from("Input");
+            for input in &buffers.inputs(machine) {
+                text = format!("{}\n{:?} - {}/{}", text, input.item_type, input.buffer.current, input.buffer.max);
+            }
+
+            input_label.0 = text;
+        }
+
+        if outputs_changed {
+            let mut output_label = label_query.get_mut(output_label.0).unwrap();
+            let mut text = String::from("Output");
+            for output in &buffers.outputs(machine) {
+                text = format!("{}\n{:?} - {}/{}", text, output.item_type, output.buffer.current, output.buffer.max);
+            }
+
+            output_label.0 = text;
+        }
+
+        if status.is_changed() || matches!(*status, MachineStatus::Working(_)) {
+            let mut status_label = label_query.get_mut(status_label.0).unwrap();
+            status_label.0 = status.describe(disabled, tick.0);
+        }
+    }
+}
+
+pub fn spawn_machine(commands: &mut Commands, grid: &mut Grid, name: &str, recipe: Recipe, cell: IVec2, rotation: Rotation) -> Result<Entity, PlacementError> {
+    let footprint = rotation.rotate_footprint(recipe.machine_kind.footprint());
+    grid.check(cell, footprint)?;
+
+    let area = Grid::rect(cell, footprint);
+    let machine = commands.spawn((
+        recipe.machine_kind,
+        recipe,
+        MachineStatus::Idle,
+        GridPos(cell),
+        rotation,
+        Transform::from_translation(area.center().extend(0.0)),
+    )).id();
+    grid.occupy(machine, cell, footprint)?;
+    commands.entity(machine).observe(inspect_on_click(machine)).observe(start_drag).observe(track_drag).observe(end_drag).observe(couple_on_drop);
+    create_label(commands, name, machine, area.size());
+    attach_buffers(commands, machine, recipe, rotation);
+
+    Ok(machine)
+}
+
+/// Gives `machine` the buffers and connectors `recipe` needs
+pub fn attach_buffers(commands: &mut Commands, machine: Entity, recipe: Recipe, rotation: Rotation) {
+    let size = machine_size(recipe.machine_kind, rotation);
+    let inputs: Vec<ItemType> = recipe.inputs.iter().flatten().map(|input| input.item_type).collect();
+
+    if !inputs.is_empty() {
+        let input_bank = InputBank::with_capacity(inputs.len());
+        // The bank goes in first, inserting it over the one spawning the connectors builds up would unlink them all
+        commands.entity(machine).insert(input_bank).with_related_entities::<MachineInput>(|spawner| {
+            for (i, item_type) in inputs.iter().enumerate() {
+                spawner.spawn((
+                    BufferType(*item_type),
+                    BufferSlot(i),
+                    ItemBuffer::with_capacity(recipe.buffer_size),
+                    Sprite::from_color(Color::linear_rgb(0.25, 0.5, 1.0), Vec2::splat(10.0)),
+                    Transform::from_translation(port_offset(size, rotation, false, i, inputs.len()).extend(1.0)),
+                    ChildOf(machine),
+                ));
+            }
+        });
+    }
+
+    let outputs: Vec<ItemType> = recipe.outputs.iter().flatten().map(|output| output.item_type).collect();
+
+    if !outputs.is_empty() {
+        let output_bank = OutputBank::with_capacity(outputs.len());
+        // The bank goes in first, inserting it over the one spawning the connectors builds up would unlink them all
+        commands.entity(machine).insert(output_bank).with_related_entities::<MachineOutput>(|spawner| {
+            for (i, item_type) in outputs.iter().enumerate() {
+                spawner.spawn((
+                    BufferType(*item_type),
+                    BufferSlot(i),
+                    ItemBuffer::with_capacity(recipe.buffer_size),
+                    Sprite::from_color(Color::linear_rgb(1.0, 0.5, 0.0), Vec2::splat(10.0)),
+                    Transform::from_translation(port_offset(size, rotation, true, i, outputs.len()).extend(1.0)),
+                    ChildOf(machine),
+                ));
+            }
+        });
+    }
+
+    // Once the connectors are there, their Capacities, the Mult and research upgrades can be applied
+    commands.queue(move |world: &mut World| fit_buffers(world, machine));
+}
+
+/// Switches `machine` over to `recipe`, rebuilding its buffers and connectors.
+/// Items carry over where the new recipe has room for them and go to the Inventory otherwise, as do the inputs of a craft in progress
+pub fn set_recipe(commands: &mut Commands, machine: Entity, recipe: Recipe) {
+    commands.queue(move |world: &mut World| {
+        let mut contents = machine_contents(world, machine);
+        let mut entity = world.entity_mut(machine);
+        let rotation = entity.get::<Rotation>().copied().unwrap_or_default();
+        entity.despawn_related::<InputBank>().despawn_related::<OutputBank>().insert((recipe, MachineStatus::Idle));
+
+        attach_buffers(&mut world.commands(), machine, recipe, rotation);
+        world.flush();
+
+        let connectors: Vec<Entity> = world.get::<InputBank>(machine).map(|bank| bank.get().clone()).unwrap_or_default().into_iter()
+            .chain(world.get::<OutputBank>(machine).map(|bank| bank.get().clone()).unwrap_or_default())
+            .collect();
+        for connector in connectors {
+            let Some(BufferType(item_type)) = world.get::<BufferType>(connector).cloned() else { continue };
+            let Some(mut buffer) = world.get_mut::<ItemBuffer>(connector) else { continue };
+            for stack in contents.iter_mut().filter(|stack| stack.item_type == item_type) {
+                let moved = stack.amount.min(buffer.remaining());
+                buffer.current += moved;
+                stack.amount -= moved;
+            }
+        }
+        world.resource_mut::<Inventory>().add_stacks(contents);
+    });
+}
+
+/// Moves `machine` to `cell` facing `rotation`. It stays the same entity, keeping its buffers, craft and couplings
+pub fn move_machine(world: &mut World, machine: Entity, cell: IVec2, rotation: Rotation) -> Result<(), PlacementError> {
+    let Some(kind) = world.get::<MachineKind>(machine).copied() else { return Ok(()) };
+    let footprint = rotation.rotate_footprint(kind.footprint());
+    let mut grid = world.resource_mut::<Grid>();
+    grid.check_except(cell, footprint, machine)?;
+    grid.vacate(machine);
+    grid.occupy(machine, cell, footprint)?;
+
+    let area = Grid::rect(cell, footprint);
+    let size = area.size();
+    let mut entity = world.entity_mut(machine);
+    entity.insert((GridPos(cell), rotation));
+    if let Some(mut transform) = entity.get_mut::<Transform>() {
+        transform.translation = area.center().extend(transform.translation.z);
+    }
+    if let Some(mut sprite) = entity.get_mut::<Sprite>() {
+        sprite.custom_size = Some(size);
+    }
+    let labels = [entity.get::<NameText>().map(|text| text.0), entity.get::<StatusText>().map(|text| text.0), entity.get::<InputBufferText>().map(|text| text.0), entity.get::<OutputBufferText>().map(|text| text.0)];
+    for (label, offset) in labels.into_iter().zip(label_offsets(size)) {
+        if let Some(mut transform) = label.and_then(|label| world.get_mut::<Transform>(label)) {
+            transform.translation = offset;
+        }
+    }
+
+    // The ports go on the sides it faces now
+    let inputs: Vec<Entity> = world.get::<InputBank>(machine).map(|bank| bank.get().clone()).unwrap_or_default();
+    let outputs: Vec<Entity> = world.get::<OutputBank>(machine).map(|bank| bank.get().clone()).unwrap_or_default();
+    for (output, connectors) in [(false, inputs), (true, outputs)] {
+        for connector in &connectors {
+            let Some(BufferSlot(slot)) = world.get::<BufferSlot>(*connector).copied() else { continue };
+            if let Some(mut transform) = world.get_mut::<Transform>(*connector) {
+                transform.translation = port_offset(size, rotation, output, slot, connectors.len()).extend(1.0);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes `machine` along with its connectors, labels and couplings.
+/// Everything it held, and whatever it cost to build, goes to the Inventory
+pub fn deconstruct_machine(commands: &mut Commands, machine: Entity) {
+    commands.queue(move |world: &mut World| {
+        if world.get_entity(machine).is_err() { return; }
+
+        let mut refund = machine_contents(world, machine);
+        refund.extend(world.get::<BuildCost>(machine).map(|cost| cost.0.clone()).unwrap_or_default());
+
+        world.resource_mut::<Inventory>().add_stacks(refund);
+        world.resource_mut::<Grid>().vacate(machine);
+        world.entity_mut(machine).despawn();
+    });
+}
+
+/// Every item held by `machine`, including the inputs already taken for a craft in progress
+pub fn machine_contents(world: &World, machine: Entity) -> Vec<ItemStack> {
+    let mut contents: Vec<ItemStack> = Vec::new();
+    let buffers = input_buffers(world, machine).into_iter().chain(output_buffers(world, machine));
+    contents.extend(buffers.map(|buf| ItemStack::new(buf.item_type, buf.buffer.current)));
+
+    if let (Some(MachineStatus::Working(working)), Some(recipe)) = (world.get::<MachineStatus>(machine), world.get::<Recipe>(machine)) {
+        contents.extend(recipe.inputs.iter().filter_map(|i| *i).map(|input| ItemStack::new(input.item_type, input.amount * working.amount)));
+    }
+
+    contents.retain(|stack| stack.amount > 0);
+    contents
+}
+
+/// Couples the first free `item_type` OutputConnector of `src` to the first free `item_type` InputConnector of `dest`
+pub fn bind_output(commands: &mut Commands, src: Entity, dest: Entity, item_type: ItemType) {
+    commands.queue(move |world: &mut World| {
+        match find_coupling(world, src, dest, item_type) {
+            Ok((output, input)) => { world.entity_mut(output).insert(OutputPort(input)); },
+            Err(err) => warn!("Can't couple {item_type:?} from {src} to {dest}: {err:?}"),
+        }
+    });
+}
+
+/// Picks the connectors `bind_output` would couple, checking the coupling is allowed
+pub fn find_coupling(world: &World, src: Entity, dest: Entity, item_type: ItemType) -> Result<(Entity, Entity), MachineBindError> {
+    let output_bank = world.get::<OutputBank>(src).ok_or(MachineBindError::OutputDoesNotExist)?;
+    let input_bank = world.get::<InputBank>(dest).ok_or(MachineBindError::InputDoesNotExist)?;
+
+    let output = output_bank.iter().find(|connector| {
+        world.get::<BufferType>(*connector).is_some_and(|buffer_type| buffer_type.0 == item_type) && world.get::<OutputPort>(*connector).is_none()
+    }).ok_or(MachineBindError::NoFreeOutputs)?;
+    let input = input_bank.iter().find(|connector| {
+        world.get::<BufferType>(*connector).is_some_and(|buffer_type| buffer_type.0 == item_type) && world.get::<InputPort>(*connector).is_none()
+    }).ok_or(MachineBindError::NoFreeInputs)?;
+
+    check_coupling(world, output, input)?;
+    Ok((output, input))
+}
+
+/// Checks `output` can be coupled to `input`: both are free, carry the same items, and satisfy the CouplingRules
+pub fn check_coupling(world: &World, output: Entity, input: Entity) -> Result<(), MachineBindError> {
+    let (Some(MachineOutput(src)), Some(BufferType(output_type))) = (world.get::<MachineOutput>(output), world.get::<BufferType>(output)) else { return Err(MachineBindError::OutputDoesNotExist) };
+    let (Some(MachineInput(dest)), Some(BufferType(input_type))) = (world.get::<MachineInput>(input), world.get::<BufferType>(input)) else { return Err(MachineBindError::InputDoesNotExist) };
+    let (src, dest) = (*src, *dest);
+
+    if output_type != input_type {
+        return Err(MachineBindError::InvalidInput);
+    }
+    if world.get::<OutputPort>(output).is_some() {
+        return Err(MachineBindError::NoFreeOutputs);
+    }
+    if world.get::<InputPort>(input).is_some() {
+        return Err(MachineBindError::NoFreeInputs);
+    }
+
+    coupling_allowed(world, src, dest)
+}
+
+/// Whether CouplingRules allow coupling `src` to `dest` where they are now
+pub fn coupling_allowed(world: &World, src: Entity, dest: Entity) -> Result<(), MachineBindError> {
+    if world.get_resource::<CouplingRules>().is_some_and(|rules| rules.require_adjacent) {
+        let placement = |machine: Entity| Some((*world.get::<GridPos>(machine)?, *world.get::<Rotation>(machine)?, *world.get::<MachineKind>(machine)?));
+        let (Some((src_pos, src_rotation, src_kind)), Some((dest_pos, dest_rotation, dest_kind))) = (placement(src), placement(dest)) else { return Err(MachineBindError::NotAdjacent) };
+        let grid = world.resource::<Grid>();
+
+        if !grid.ports_face(src, (src_pos, src_rotation, src_rotation.rotate_footprint(src_kind.footprint())), dest, (dest_pos, dest_rotation, dest_rotation.rotate_footprint(dest_kind.footprint()))) {
+            return Err(MachineBindError::NotAdjacent);
+        }
+    }
+
+    Ok(())
+}
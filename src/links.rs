@@ -0,0 +1,70 @@
+use bevy::prelude::*;
+
+use crate::{grid::Rotation, pipeline::machine::{BufferType, CouplingFlow, MachineInput, MachineOutput, OutputPort}};
+
+const IDLE_COLOR: Color = Color::linear_rgba(0.4, 0.4, 0.4, 0.35);
+const CURVE_SEGMENTS: usize = 24;
+const MAX_DOTS: f32 = 8.0;
+const DOT_SPEED: f32 = 0.5;
+const DOT_RADIUS: f32 = 3.0;
+const SATURATED_OFFSET: f32 = 1.5;
+
+#[derive(Default, Reflect, GizmoConfigGroup)]
+pub struct LinkGizmos;
+
+pub fn configure_link_gizmos(mut config_store: ResMut<GizmoConfigStore>) {
+    let (config, _) = config_store.config_mut::<LinkGizmos>();
+    config.line.width = 2.0;
+}
+
+/// Draws every coupling as a curve between its port nodes.
+/// Idle links are dimmed, busy ones get dots moving along them (more dots for more throughput),
+/// and links that can't keep up with their output buffer are drawn thicker
+pub fn draw_couplings(mut gizmos: Gizmos<LinkGizmos>, time: Res<Time>, coupling_query: Query<(&OutputPort, &MachineOutput, &BufferType, &CouplingFlow, &GlobalTransform)>, port_query: Query<(&MachineInput, &GlobalTransform)>, rotation_query: Query<&Rotation>) {
+    let facing = |machine: Entity| rotation_query.get(machine).copied().unwrap_or_default().facing().as_vec2();
+
+    for (OutputPort(dest), MachineOutput(src_machine), BufferType(item_type), flow, src_transform) in &coupling_query {
+        let Ok((MachineInput(dest_machine), dest_transform)) = port_query.get(*dest) else { continue };
+        let link = Link { start: src_transform.translation().truncate(), leave: facing(*src_machine), end: dest_transform.translation().truncate(), enter: facing(*dest_machine) };
+
+        let color = if flow.is_idle() { IDLE_COLOR } else { item_type.color() };
+        gizmos.linestrip_2d((0..=CURVE_SEGMENTS).map(|i| link.point(i as f32 / CURVE_SEGMENTS as f32)), color);
+
+        if flow.backed_up {
+            let across = (link.end - link.start).normalize_or(link.leave).perp() * SATURATED_OFFSET;
+            for offset in [across, -across] {
+                gizmos.linestrip_2d((0..=CURVE_SEGMENTS).map(|i| link.point(i as f32 / CURVE_SEGMENTS as f32) + offset), color);
+            }
+        }
+
+        if flow.is_idle() { continue; }
+
+        let dots = (flow.rate * MAX_DOTS).ceil().clamp(1.0, MAX_DOTS);
+        let phase = (time.elapsed_secs() * DOT_SPEED).fract();
+        for i in 0..dots as usize {
+            let t = (phase + i as f32 / dots).fract();
+            gizmos.circle_2d(link.point(t), DOT_RADIUS, color);
+        }
+    }
+}
+
+/// A coupling's curve from its output port to its input port
+struct Link {
+    start: Vec2,
+    /// Direction the output port faces, the way its machine is rotated
+    leave: Vec2,
+    end: Vec2,
+    /// Direction the input port's machine faces, items come in heading that way
+    enter: Vec2,
+}
+
+impl Link {
+    /// Point `t` of the way along the link, leaving and entering the ports straight out of the sides they're on
+    fn point(&self, t: f32) -> Vec2 {
+        let reach = (self.start.distance(self.end) * 0.5).max(40.0);
+        let (p0, p1, p2, p3) = (self.start, self.start + self.leave * reach, self.end - self.enter * reach, self.end);
+        let u = 1.0 - t;
+
+        p0 * u * u * u + p1 * 3.0 * u * u * t + p2 * 3.0 * u * t * t + p3 * t * t * t
+    }
+}
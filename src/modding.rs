@@ -0,0 +1,208 @@
+use std::{cell::RefCell, hash::{Hash, Hasher}};
+
+use bevy::{ecs::system::ScheduleSystem, prelude::*};
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+use crate::{pipeline::{machine::MachineKind, recipe::{Recipe, Recipes}}, plugin::{FactorySchedule, FactorySet}, research::{Research, ResearchTree, Technology, Unlock}, ItemType};
+
+/// An item type added by a mod. Declare it as a `static` and pass it to FactoryAppExt::add_item
+pub struct CustomItem {
+    pub name: &'static str,
+    pub color: Color,
+}
+
+impl PartialEq for CustomItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for CustomItem {}
+
+impl Hash for CustomItem {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+    }
+}
+
+impl PartialOrd for CustomItem {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CustomItem {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.name.cmp(other.name)
+    }
+}
+
+impl std::fmt::Debug for CustomItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+/// A machine kind added by a mod. Declare it as a `static` and pass it to FactoryAppExt::add_machine_kind
+pub struct CustomMachine {
+    pub name: &'static str,
+    /// Cells it takes up on the grid when facing east
+    pub footprint: UVec2,
+    /// Inventory item it's built from in progression mode, None if it's free
+    pub item: Option<ItemType>,
+}
+
+impl std::fmt::Debug for CustomMachine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+/// Everything mods have added
+pub struct Registry {
+    pub items: Vec<ItemType>,
+    pub machines: Vec<MachineKind>,
+}
+
+impl Registry {
+    /// Built-in item types and machine kinds are always known
+    pub fn knows_item(&self, item_type: ItemType) -> bool {
+        !matches!(item_type, ItemType::Custom(_)) || self.items.contains(&item_type)
+    }
+
+    pub fn knows_machine(&self, machine_kind: MachineKind) -> bool {
+        !matches!(machine_kind, MachineKind::Custom(_)) || self.machines.contains(&machine_kind)
+    }
+
+    pub fn item(&self, name: &str) -> Option<&'static CustomItem> {
+        self.items.iter().find_map(|item_type| match item_type {
+            ItemType::Custom(item) if item.name == name => Some(*item),
+            _ => None,
+        })
+    }
+
+    pub fn machine(&self, name: &str) -> Option<&'static CustomMachine> {
+        self.machines.iter().find_map(|machine_kind| match machine_kind {
+            MachineKind::Custom(machine) if machine.name == name => Some(*machine),
+            _ => None,
+        })
+    }
+
+    /// Runs `load` with custom item types and machine kinds deserializing by looking their names up in this registry.
+    /// Outside of it they fail to deserialize
+    pub fn loading<T>(&self, load: impl FnOnce() -> T) -> T {
+        let previous = LOADING.replace(Some(self.clone()));
+        let loaded = load();
+        LOADING.set(previous);
+        loaded
+    }
+}
+
+thread_local! {
+    /// Registry custom names are looked up in while deserializing, see Registry::loading
+    static LOADING: RefCell<Option<Registry>> = const { RefCell::new(None) };
+}
+
+/// Looks up a custom name saved by `serialize` in the registry being loaded with
+fn lookup<'de, D: Deserializer<'de>, T>(deserializer: D, what: &str, find: impl FnOnce(&Registry, &str) -> Option<T>) -> Result<T, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    LOADING.with_borrow(|registry| match registry {
+        Some(registry) => find(registry, &name).ok_or_else(|| D::Error::custom(format!("unknown {what} {name}, is the mod that adds it installed?"))),
+        None => Err(D::Error::custom(format!("can't load custom {what} {name} outside of Registry::loading"))),
+    })
+}
+
+/// Serde for ItemType::Custom, by the item's name
+pub(crate) mod custom_item {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(item: &&'static CustomItem, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(item.name)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<&'static CustomItem, D::Error> {
+        lookup(deserializer, "item", Registry::item)
+    }
+}
+
+/// Serde for MachineKind::Custom, by the machine's name
+pub(crate) mod custom_machine {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(machine: &&'static CustomMachine, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(machine.name)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<&'static CustomMachine, D::Error> {
+        lookup(deserializer, "machine", Registry::machine)
+    }
+}
+
+/// Registration API for mods, to be called from a Plugin's `build`. Mod plugins can be added before or after
+/// FactoryPlugin, except for add_machine_behaviour, which needs FactoryPlugin's schedule and so has to come after it
+pub trait FactoryAppExt {
+    fn add_item(&mut self, item: &'static CustomItem) -> &mut Self;
+
+    /// Adds a machine kind. Its recipes craft like any other machine's, anything more goes in add_machine_behaviour
+    fn add_machine_kind(&mut self, machine: &'static CustomMachine) -> &mut Self;
+
+    /// Adds systems for the per-tick behaviour of custom machines. They run every tick in FactorySet::Consume,
+    /// after items have moved between machines. FactoryPlugin has to be added first
+    fn add_machine_behaviour<M>(&mut self, behaviour: impl IntoScheduleConfigs<ScheduleSystem, M>) -> &mut Self;
+
+    /// Adds a recipe, available from the start. Panics if it uses a custom item or machine kind that wasn't added first
+    fn add_recipe(&mut self, recipe: Recipe) -> &mut Self;
+
+    /// Adds a technology to the research tree
+    fn add_technology(&mut self, technology: Technology) -> &mut Self;
+}
+
+impl FactoryAppExt for App {
+    fn add_item(&mut self, item: &'static CustomItem) -> &mut Self {
+        self.world_mut().get_resource_or_init::<Registry>().items.push(ItemType::Custom(item));
+        self
+    }
+
+    fn add_machine_kind(&mut self, machine: &'static CustomMachine) -> &mut Self {
+        let mut registry = self.world_mut().get_resource_or_init::<Registry>();
+        if let Some(item) = machine.item {
+            assert!(registry.knows_item(item), "{machine:?} is built from {item:?}, which hasn't been added");
+        }
+
+        registry.machines.push(MachineKind::Custom(machine));
+        self
+    }
+
+    fn add_machine_behaviour<M>(&mut self, behaviour: impl IntoScheduleConfigs<ScheduleSystem, M>) -> &mut Self {
+        let FactorySchedule(schedule) = *self.world().get_resource::<FactorySchedule>().expect("FactoryPlugin should be added before machine behaviours");
+        self.add_systems(schedule, behaviour.in_set(FactorySet::Consume))
+    }
+
+    fn add_recipe(&mut self, recipe: Recipe) -> &mut Self {
+        let registry = self.world_mut().get_resource_or_init::<Registry>();
+        assert!(registry.knows_machine(recipe.machine_kind), "{} is made by {:?}, which hasn't been added", recipe.name(), recipe.machine_kind);
+        for stack in recipe.inputs.iter().chain(recipe.outputs.iter()).filter_map(|s| *s) {
+            assert!(registry.knows_item(stack.item_type), "{} uses {:?}, which hasn't been added", recipe.name(), stack.item_type);
+        }
+
+        self.world_mut().get_resource_or_insert_with(Recipes::init).inner.push(recipe);
+        self.world_mut().get_resource_or_insert_with(ResearchTree::init).starting.push(Unlock::Recipe(recipe.name()));
+        refresh_research(self.world_mut());
+        self
+    }
+
+    fn add_technology(&mut self, technology: Technology) -> &mut Self {
+        self.world_mut().get_resource_or_insert_with(ResearchTree::init).technologies.push(technology);
+        refresh_research(self.world_mut());
+        self
+    }
+}
+
+pub(crate) fn refresh_research(world: &mut World) {
+    world.init_resource::<Research>();
+    world.resource_scope(|world, tree: Mut<ResearchTree>| {
+        world.resource_mut::<Research>().refresh_unlocks(&tree);
+    });
+}
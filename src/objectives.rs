@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use bevy::{platform::collections::HashMap, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{inventory::{Deposit, Inventory}, pipeline::{machine::{BufferType, ItemBuffer, MachineInput, MachineKind, MachineStats}, SimTick}, ItemType};
+
+#[derive(Resource, Clone, Debug, Default)]
+/// Everything delivered to sinks so far
+pub struct Deliveries {
+    counts: HashMap<ItemType, u64>,
+}
+
+impl Deliveries {
+    pub fn add(&mut self, item_type: ItemType, amount: u64) {
+        *self.counts.entry(item_type).or_default() += amount;
+    }
+
+    pub fn count(&self, item_type: ItemType) -> u64 {
+        self.counts.get(&item_type).copied().unwrap_or_default()
+    }
+
+    /// Every item delivered so far, in a stable order
+    pub fn iter(&self) -> impl Iterator<Item = (ItemType, u64)> {
+        let mut counts: Vec<(ItemType, u64)> = self.counts.iter().map(|(item_type, count)| (*item_type, *count)).collect();
+        counts.sort();
+        counts.into_iter()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ObjectiveState {
+    InProgress,
+    Completed { tick: u64 },
+    /// Ran out of time
+    Failed,
+}
+
+#[derive(Clone, Debug)]
+/// Deliver `amount` of `item_type` to sinks, optionally before a deadline
+pub struct Objective {
+    pub item_type: ItemType,
+    pub amount: u64,
+    /// Simulated time from the start of the scenario the objective has to be met by, if it has a time limit
+    pub deadline: Option<Duration>,
+    pub delivered: u64,
+    pub state: ObjectiveState,
+}
+
+impl Objective {
+    pub fn deliver(item_type: ItemType, amount: u64) -> Self {
+        Self { item_type, amount, deadline: None, delivered: 0, state: ObjectiveState::InProgress }
+    }
+
+    /// Gives the objective a time limit, in simulated time from the start of the scenario
+    pub fn within(mut self, duration: Duration) -> Self {
+        self.deadline = Some(duration);
+        self
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+/// The scenario's goals
+pub struct Objectives(pub Vec<Objective>);
+
+impl Objectives {
+    /// Counts a delivery towards every objective still in progress that asks for `item_type`
+    pub fn credit(&mut self, item_type: ItemType, amount: u64) {
+        for objective in self.0.iter_mut().filter(|o| o.item_type == item_type && o.state == ObjectiveState::InProgress) {
+            objective.delivered += amount;
+        }
+    }
+}
+
+#[derive(Message, Clone, Debug)]
+/// Sent once when an objective is met
+pub struct ObjectiveCompleted {
+    pub index: usize,
+    pub objective: Objective,
+}
+
+#[derive(Message, Clone, Debug)]
+/// Sent once when an objective's deadline passes without it being met
+pub struct ObjectiveFailed {
+    pub index: usize,
+    pub objective: Objective,
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct ObjectivesHud;
+
+/// Empties the sinks' buffers that were filled since the last tick, crediting the items to Deliveries and the objectives
+/// still in progress. Depositing sinks put the items in the Inventory as well
+pub fn deliver_to_sinks(mut deliveries: ResMut<Deliveries>, mut objectives: ResMut<Objectives>, mut inventory: ResMut<Inventory>, mut sink_query: Query<(&MachineKind, &mut MachineStats, Has<Deposit>)>, mut buffer_query: Query<(&MachineInput, &BufferType, &mut ItemBuffer), Changed<ItemBuffer>>) {
+    for (MachineInput(machine), BufferType(item_type), mut buffer) in &mut buffer_query {
+        if buffer.current == 0 { continue; }
+        let Ok((MachineKind::Sink, mut stats, deposit)) = sink_query.get_mut(*machine) else { continue };
+        let amount = std::mem::take(&mut buffer.current);
+        stats.items_consumed += amount;
+        deliveries.add(*item_type, amount);
+        objectives.credit(*item_type, amount);
+        if deposit {
+            inventory.add(*item_type, amount);
+        }
+    }
+}
+
+pub fn check_objectives(tick: Res<SimTick>, time: Res<Time<Fixed>>, mut objectives: ResMut<Objectives>, mut completed: MessageWriter<ObjectiveCompleted>, mut failed: MessageWriter<ObjectiveFailed>) {
+    for (index, objective) in objectives.0.iter_mut().enumerate().filter(|(_, o)| o.state == ObjectiveState::InProgress) {
+        if objective.delivered >= objective.amount {
+            objective.state = ObjectiveState::Completed { tick: tick.0 };
+            completed.write(ObjectiveCompleted { index, objective: objective.clone() });
+        } else if objective.deadline.is_some_and(|deadline| tick.elapsed(&time) >= deadline) {
+            objective.state = ObjectiveState::Failed;
+            failed.write(ObjectiveFailed { index, objective: objective.clone() });
+        }
+    }
+}
+
+pub fn announce_objectives(mut completed: MessageReader<ObjectiveCompleted>, mut failed: MessageReader<ObjectiveFailed>) {
+    for ObjectiveCompleted { index, objective } in completed.read() {
+        info!("Objective {} complete: delivered {} {:?}", index + 1, objective.amount, objective.item_type);
+    }
+    for ObjectiveFailed { index, objective } in failed.read() {
+        info!("Objective {} failed: delivered {}/{} {:?} in time", index + 1, objective.delivered, objective.amount, objective.item_type);
+    }
+}
+
+pub fn setup_objectives_hud(mut commands: Commands) {
+    commands.spawn((
+        ObjectivesHud,
+        Text::new(""),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            left: px(10),
+            top: px(30),
+            ..default()
+        },
+    ));
+}
+
+pub fn update_objectives_hud(tick: Res<SimTick>, time: Res<Time<Fixed>>, objectives: Res<Objectives>, mut hud_query: Query<&mut Text, With<ObjectivesHud>>) {
+    let Ok(mut hud) = hud_query.single_mut() else { return };
+
+    hud.0 = objectives.0.iter().map(|objective| {
+        let goal = format!("Deliver {} {:?}: {}/{}", objective.amount, objective.item_type, objective.delivered.min(objective.amount), objective.amount);
+        match (objective.state, objective.deadline) {
+            (ObjectiveState::Completed { .. }, _) => format!("{goal} - done"),
+            (ObjectiveState::Failed, _) => format!("{goal} - failed"),
+            (ObjectiveState::InProgress, Some(deadline)) => {
+                let seconds = deadline.saturating_sub(tick.elapsed(&time)).as_secs();
+                format!("{goal} - {}:{:02} left", seconds / 60, seconds % 60)
+            },
+            (ObjectiveState::InProgress, None) => goal,
+        }
+    }).collect::<Vec<String>>().join("\n");
+}
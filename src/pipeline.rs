@@ -1,7 +1,30 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
 use crate::{pipeline::machine::ItemBuffer, ItemType};
 
 pub mod recipe;
 pub mod machine;
+pub mod circuit;
+pub mod wake;
+pub mod fast_forward;
+pub mod validation;
+
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Number of fixed ticks simulated so far
+pub struct SimTick(pub u64);
+
+impl SimTick {
+    /// Simulated time since the first tick, at the fixed timestep's tick rate
+    pub fn elapsed(&self, time: &Time<Fixed>) -> Duration {
+        time.timestep().mul_f64(self.0 as f64)
+    }
+}
+
+pub fn advance_tick(mut tick: ResMut<SimTick>) {
+    tick.0 += 1;
+}
 
 // #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 // pub enum PortStatus {
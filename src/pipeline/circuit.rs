@@ -0,0 +1,163 @@
+use bevy::{ecs::system::SystemParam, platform::collections::HashMap, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{pipeline::{machine::MachineBuffers, recipe::Recipe, wake::wake_machine, IoBuffer}, ItemType};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Serialize, Deserialize)]
+/// A wire sensors write to and enable conditions read from. Sensors sharing a channel add up
+pub struct Channel(pub u32);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum SensorReading {
+    /// Items of this type waiting in the machine's input buffers
+    InputFill(ItemType),
+    /// Items of this type waiting in the machine's output buffers
+    OutputFill(ItemType),
+    /// Items of this type anywhere in the machine, for reading what a Storage holds
+    Stored(ItemType),
+}
+
+#[derive(Component, Clone, Copy, Debug, Serialize, Deserialize)]
+#[require(SensorValue)]
+/// Reads a value off the machine it's attached to and puts it on a channel every tick
+pub struct Sensor {
+    pub reads: SensorReading,
+    pub channel: Channel,
+}
+
+#[derive(Component, Clone, Copy, Debug, Default)]
+/// What a Sensor read last tick
+pub struct SensorValue(pub u64);
+
+#[derive(Component, Clone, Copy, Debug)]
+#[relationship(relationship_target = Sensors)]
+/// Connects a Sensor to the machine it reads from
+pub struct SensorOf(pub Entity);
+
+#[derive(Component, Clone, Debug)]
+#[relationship_target(relationship = SensorOf, linked_spawn)]
+/// Connects a machine to the Sensors reading from it
+pub struct Sensors(Vec<Entity>);
+
+#[derive(Resource, Clone, Debug, Default)]
+/// Value on every channel as of the last tick
+pub struct Signals {
+    values: HashMap<Channel, u64>,
+}
+
+impl Signals {
+    pub fn get(&self, channel: Channel) -> u64 {
+        self.values.get(&channel).copied().unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    pub const ALL: [Comparison; 6] = [Comparison::Less, Comparison::LessOrEqual, Comparison::Greater, Comparison::GreaterOrEqual, Comparison::Equal, Comparison::NotEqual];
+
+    pub fn holds(&self, left: u64, right: u64) -> bool {
+        match self {
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Equal => "=",
+            Comparison::NotEqual => "!=",
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[component(on_remove = wake_machine)]
+/// A machine only starts crafts while `channel` compares to `value`, e.g. only while channel 0 < 20
+pub struct EnableCondition {
+    pub channel: Channel,
+    pub comparison: Comparison,
+    pub value: u64,
+}
+
+impl EnableCondition {
+    pub fn holds(&self, signals: &Signals) -> bool {
+        self.comparison.holds(signals.get(self.channel), self.value)
+    }
+}
+
+impl std::fmt::Display for EnableCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ch{} {} {}", self.channel.0, self.comparison.symbol(), self.value)
+    }
+}
+
+#[derive(SystemParam)]
+/// Reads back sensor values and signals, for showing them to the player
+pub struct CircuitReadout<'w, 's> {
+    signals: Res<'w, Signals>,
+    sensor_query: Query<'w, 's, (&'static Sensor, &'static SensorValue)>,
+}
+
+impl CircuitReadout<'_, '_> {
+    /// A line for the enable condition, if any, and one per sensor
+    pub fn describe(&self, condition: Option<&EnableCondition>, sensors: Option<&Sensors>) -> String {
+        let mut text = String::new();
+        if let Some(condition) = condition {
+            text = format!("{text}\nEnabled while {condition} (now {})", self.signals.get(condition.channel));
+        }
+        for (sensor, value) in sensors.iter().flat_map(|sensors| sensors.iter()).filter_map(|sensor| self.sensor_query.get(sensor).ok()) {
+            text = format!("{text}\nSensor: {:?} -> ch{} = {}", sensor.reads, sensor.channel.0, value.0);
+        }
+        text
+    }
+}
+
+/// Has every Sensor read its machine and sums the readings into Signals, ready for this tick's ready_craft
+pub fn read_sensors(mut signals: ResMut<Signals>, mut sensor_query: Query<(&Sensor, &SensorOf, &mut SensorValue)>, buffers: MachineBuffers) {
+    signals.values.clear();
+
+    for (sensor, SensorOf(machine), mut value) in &mut sensor_query {
+        let count = |buffers: &[IoBuffer], item_type: ItemType| buffers.iter().filter(|b| b.item_type == item_type).map(|b| b.buffer.current).sum::<u64>();
+        let (inputs, outputs) = (buffers.inputs(*machine), buffers.outputs(*machine));
+
+        value.0 = match sensor.reads {
+            SensorReading::InputFill(item_type) => count(&inputs, item_type),
+            SensorReading::OutputFill(item_type) => count(&outputs, item_type),
+            SensorReading::Stored(item_type) => count(&inputs, item_type) + count(&outputs, item_type),
+        };
+        *signals.values.entry(sensor.channel).or_default() += value.0;
+    }
+}
+
+/// Everything a sensor on a machine making `recipe` could usefully read
+pub fn sensor_readings(recipe: &Recipe) -> Vec<SensorReading> {
+    let inputs = recipe.inputs.iter().filter_map(|i| *i).map(|i| i.item_type);
+    let outputs = recipe.outputs.iter().filter_map(|o| *o).map(|o| o.item_type);
+
+    inputs.clone().map(SensorReading::InputFill)
+        .chain(outputs.clone().map(SensorReading::OutputFill))
+        .chain(inputs.chain(outputs).map(SensorReading::Stored))
+        .collect()
+}
+
+/// Attaches a sensor to `machine`
+pub fn add_sensor(commands: &mut Commands, machine: Entity, reads: SensorReading, channel: Channel) -> Entity {
+    commands.spawn((Sensor { reads, channel }, SensorOf(machine))).id()
+}
@@ -0,0 +1,615 @@
+use bevy::{platform::collections::HashMap, prelude::*};
+
+use crate::{inventory::{Deposit, Inventory}, objectives::{Deliveries, ObjectiveState, Objectives}, pipeline::{circuit::{Channel, EnableCondition, Sensor, SensorOf, SensorReading}, machine::{BufferSlot, BufferType, CouplingFlow, Disabled, InputBank, ItemBuffer, MachineKind, MachineStats, MachineStatus, Mult, OutputBank, OutputPort}, recipe::Recipe, SimTick}, plugin::FactorySchedule, research::{Research, ResearchTree}, ItemType};
+
+/// Shorter skips than this just get stepped through, setting up the flow model isn't worth it
+const MIN_FAST_FORWARD_TICKS: u64 = 100;
+/// Rates and levels closer together than this count as equal
+const EPSILON: f64 = 1e-9;
+/// Rates settle in as many rounds as the longest chain of full or starved buffers, cycles can take longer
+const MAX_SOLVE_ROUNDS: usize = 1000;
+/// Buffers filling up or running dry this many times in one go means the rates aren't stable, the rest gets stepped through
+const MAX_EVENTS: usize = 10_000;
+/// Sensor readings this close to an enable condition's threshold are on it
+const THRESHOLD_EPSILON: f64 = 1e-6;
+/// Halvings to find how fast a machine held at its enable condition's threshold runs
+const THRESHOLD_ROUNDS: usize = 30;
+
+/// Advances the simulation by `ticks` without running every one of them, for skipping ahead and offline progress.
+///
+/// Each crafting machine is treated as a steady flow of `Mult / Recipe::ticks` crafts per tick, slowed down to what
+/// comes in while an input buffer is empty, and to what goes out while an output buffer is full. The factory jumps
+/// straight from one buffer saturating or draining to the next, working out crafts, buffer levels and deliveries
+/// from the rates in between. It steps through single ticks for what the rates can't tell: research completing,
+/// objectives being met or running out of time. Enable conditions hold or don't from one sensor reading crossing
+/// their threshold to the next, and a machine that would keep flipping at the threshold runs just fast enough to
+/// stay on it. Factories with mod machines can't be modelled at all, so those are stepped through tick by tick,
+/// as are skips shorter than MIN_FAST_FORWARD_TICKS.
+///
+/// Compared to running every tick, crafts, items consumed and produced and deliveries come out within 1% plus two
+/// batches (`Mult` crafts) per machine, and each buffer within one batch of the machines on either side of it.
+/// In-progress crafts finish when they're due, holding their machine back until then
+pub fn fast_forward(world: &mut World, ticks: u64) {
+    fast_forward_within(world, ticks, u64::MAX);
+}
+
+/// Like fast_forward, stepping through at most `max_steps` ticks one at a time. Gives back the ticks it didn't get to
+pub fn fast_forward_within(world: &mut World, ticks: u64, max_steps: u64) -> u64 {
+    let FactorySchedule(schedule) = *world.resource::<FactorySchedule>();
+    let modelled = FlowModel::supports(world);
+    let mut remaining = ticks;
+
+    for _ in 0..max_steps {
+        if remaining == 0 { break; }
+        if modelled && remaining >= MIN_FAST_FORWARD_TICKS {
+            let mut model = FlowModel::capture(world);
+            let elapsed = model.run(remaining as f64);
+            let advanced = (elapsed.round() as u64).min(remaining);
+            model.apply(world, advanced);
+            remaining -= advanced;
+            if remaining == 0 { break; }
+        }
+
+        world.run_schedule(schedule);
+        remaining -= 1;
+    }
+    remaining
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Consumer {
+    /// Uses up everything, crediting deliveries
+    Sink { deposit: bool },
+    /// Holds on to everything, unless it deposits into the Inventory
+    Storage { deposit: bool },
+    /// Uses up what the current technology still needs
+    Lab,
+    /// Nothing takes items out of it
+    None,
+}
+
+/// `connectors` with their slot, item type and buffer
+fn connectors(world: &World, connectors: Option<&Vec<Entity>>) -> Vec<(Entity, usize, ItemType, ItemBuffer)> {
+    connectors.into_iter().flatten()
+        .filter_map(|connector| Some((*connector, world.get::<BufferSlot>(*connector)?.0, world.get::<BufferType>(*connector)?.0, *world.get::<ItemBuffer>(*connector)?)))
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+/// Items of one type between two machines: an output buffer and the input buffer it's coupled to, taken as one.
+/// Uncoupled buffers are pools of their own
+struct Pool {
+    item_type: ItemType,
+    level: f64,
+    capacity: f64,
+    /// Crafter filling it and the items per craft
+    src: Option<(usize, f64)>,
+    /// Crafter draining it and the items per craft
+    dest: Option<(usize, f64)>,
+    /// Machine it goes into when that doesn't craft
+    consumer: Option<(Entity, Consumer)>,
+    /// OutputConnector and InputConnector whose buffers it stands for, with the input buffer's capacity
+    output: Option<Entity>,
+    input: Option<(Entity, u64)>,
+    /// OutputConnector of the coupling
+    coupling: Option<Entity>,
+    /// Items the consumer used up
+    consumed: f64,
+    /// Items that went across the coupling
+    moved: f64,
+}
+
+impl Pool {
+    fn is_empty(&self) -> bool {
+        self.level <= EPSILON
+    }
+
+    fn is_full(&self) -> bool {
+        self.level >= self.capacity - EPSILON
+    }
+
+    /// Level at which a coupled pool's input buffer is full and the rest stays in the output buffer, like push_outputs leaves it
+    fn split(&self) -> Option<f64> {
+        self.output.and(self.input).map(|(_, max)| max as f64)
+    }
+
+    /// Items a sensor on `side` of the pool reads, and how fast that changes while the pool fills by `net` per tick
+    fn reading(&self, side: Side, net: f64) -> (f64, f64) {
+        let Some(split) = self.split() else { return (self.level, net) };
+        let filling_input = self.level < split - THRESHOLD_EPSILON || (self.level <= split + THRESHOLD_EPSILON && net < 0.0);
+        match side {
+            Side::Input => (self.level.min(split), if filling_input { net } else { 0.0 }),
+            Side::Output => ((self.level - split).max(0.0), if filling_input { 0.0 } else { net }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Which of a pool's buffers a sensor reads
+enum Side {
+    Input,
+    Output,
+}
+
+#[derive(Clone, Debug)]
+/// A machine that crafts, running at `rate` crafts per tick
+struct Node {
+    machine: Entity,
+    /// Crafts per tick while nothing holds it back
+    max_rate: f64,
+    rate: f64,
+    ticks: u64,
+    inputs: Vec<usize>,
+    outputs: Vec<usize>,
+    crafts: f64,
+    /// Ticks spent crafting
+    busy: f64,
+    /// Crafts in progress when the skip started, their inputs were already taken
+    in_progress: u64,
+    /// Ticks until they finish, the machine doesn't start anything else before that
+    finishes_in: f64,
+    /// Whether they've finished and gone into the output buffers
+    finished: bool,
+    condition: Option<EnableCondition>,
+    /// Share of `max_rate` its enable condition lets it run at, in between when it's held at the threshold
+    enabled: f64,
+}
+
+impl Node {
+    fn is_finishing(&self) -> bool {
+        self.in_progress > 0 && !self.finished
+    }
+
+    /// Crafts per tick while nothing but its enable condition holds it back
+    fn cap(&self) -> f64 {
+        if self.is_finishing() { 0.0 } else { self.max_rate * self.enabled }
+    }
+}
+
+/// Fluid approximation of the factory, see fast_forward
+struct FlowModel {
+    nodes: Vec<Node>,
+    pools: Vec<Pool>,
+    /// Items labs still have to take in for the current technology
+    needed: HashMap<ItemType, f64>,
+    /// Item type and how many more are needed, for each objective in progress
+    objectives: Vec<(ItemType, f64)>,
+    /// Ticks until the first objective deadline
+    deadline: Option<f64>,
+    /// Pools and which of their buffers the sensors on each channel read
+    channels: HashMap<Channel, Vec<(usize, Side)>>,
+}
+
+impl FlowModel {
+    /// Whether the rates in `world` can be worked out at all. Mod machines can do anything in their behaviour systems
+    fn supports(world: &mut World) -> bool {
+        !world.query::<&MachineKind>().iter(world).any(|kind| matches!(kind, MachineKind::Custom(_)))
+    }
+
+    fn capture(world: &mut World) -> Self {
+        let mut pools = Vec::new();
+        let mut nodes = Vec::new();
+        // Pools by their InputConnector and OutputConnector
+        let mut input_pools: HashMap<Entity, usize> = HashMap::default();
+        let mut output_pools: HashMap<Entity, usize> = HashMap::default();
+
+        let tick = world.resource::<SimTick>().0;
+        let mut machine_query = world.query::<(Entity, &MachineKind, &Recipe, &MachineStatus, Option<&Mult>, Has<Disabled>, Has<Deposit>, Option<&InputBank>, Option<&OutputBank>, Option<&EnableCondition>)>();
+        for (machine, kind, _, _, _, _, deposit, inputs, outputs, _) in machine_query.iter(world) {
+            let consumer = match kind {
+                _ if outputs.is_some() => None,
+                MachineKind::Sink => Some(Consumer::Sink { deposit }),
+                MachineKind::Storage => Some(Consumer::Storage { deposit }),
+                MachineKind::Lab => Some(Consumer::Lab),
+                _ => Some(Consumer::None),
+            };
+
+            for (connector, _, item_type, buffer) in connectors(world, inputs.map(InputBank::get)) {
+                input_pools.insert(connector, pools.len());
+                pools.push(Pool {
+                    item_type,
+                    level: buffer.current as f64,
+                    capacity: buffer.max as f64,
+                    src: None,
+                    dest: None,
+                    consumer: consumer.map(|consumer| (machine, consumer)),
+                    output: None,
+                    input: Some((connector, buffer.max)),
+                    coupling: None,
+                    consumed: 0.0,
+                    moved: 0.0,
+                });
+            }
+        }
+
+        for (machine, _, recipe, status, mult, disabled, _, inputs, outputs, condition) in machine_query.iter(world) {
+            let Some(outputs) = outputs else { continue };
+            let index = nodes.len();
+            let mult = mult.map_or(1, |mult| mult.0);
+            let (in_progress, finishes_in) = match status {
+                MachineStatus::Working(working) => (working.amount, working.finishes_at.saturating_sub(tick) as f64),
+                MachineStatus::CraftsFinished(amount) => (*amount, 0.0),
+                _ => (0, 0.0),
+            };
+
+            let mut node = Node {
+                machine,
+                max_rate: if disabled { 0.0 } else { mult as f64 / recipe.ticks.max(1) as f64 },
+                rate: 0.0,
+                ticks: recipe.ticks.max(1),
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+                crafts: 0.0,
+                busy: 0.0,
+                in_progress,
+                finishes_in,
+                finished: false,
+                condition: condition.copied(),
+                enabled: 1.0,
+            };
+
+            for (connector, slot, _, _) in connectors(world, inputs.map(InputBank::get)) {
+                let (Some(&pool), Some(input)) = (input_pools.get(&connector), recipe.inputs.iter().flatten().nth(slot)) else { continue };
+                pools[pool].dest = Some((index, input.amount as f64));
+                node.inputs.push(pool);
+            }
+
+            for (connector, slot, item_type, buffer) in connectors(world, Some(outputs.get())) {
+                let Some(output) = recipe.outputs.iter().flatten().nth(slot) else { continue };
+                let level = buffer.current as f64;
+                let coupled = world.get::<OutputPort>(connector).and_then(|OutputPort(input)| input_pools.get(input));
+                let pool = match coupled {
+                    Some(&pool) => {
+                        pools[pool].level += level;
+                        pools[pool].capacity += buffer.max as f64;
+                        pools[pool].coupling = Some(connector);
+                        pool
+                    },
+                    _ => {
+                        pools.push(Pool {
+                            item_type,
+                            level,
+                            capacity: buffer.max as f64,
+                            src: None,
+                            dest: None,
+                            consumer: None,
+                            output: None,
+                            input: None,
+                            coupling: None,
+                            consumed: 0.0,
+                            moved: 0.0,
+                        });
+                        pools.len() - 1
+                    },
+                };
+                pools[pool].src = Some((index, output.amount as f64));
+                pools[pool].output = Some(connector);
+                output_pools.insert(connector, pool);
+                node.outputs.push(pool);
+            }
+
+            nodes.push(node);
+        }
+
+        let mut channels: HashMap<Channel, Vec<(usize, Side)>> = HashMap::default();
+        for (sensor, SensorOf(machine)) in world.query::<(&Sensor, &SensorOf)>().iter(world) {
+            let (item_type, reads_inputs, reads_outputs) = match sensor.reads {
+                SensorReading::InputFill(item_type) => (item_type, true, false),
+                SensorReading::OutputFill(item_type) => (item_type, false, true),
+                SensorReading::Stored(item_type) => (item_type, true, true),
+            };
+            let inputs = world.get::<InputBank>(*machine).filter(|_| reads_inputs).into_iter().flat_map(|bank| bank.iter())
+                .filter_map(|input| input_pools.get(&input)).map(|pool| (*pool, Side::Input));
+            let outputs = world.get::<OutputBank>(*machine).filter(|_| reads_outputs).into_iter().flat_map(|bank| bank.iter())
+                .filter_map(|output| output_pools.get(&output)).map(|pool| (*pool, Side::Output));
+            channels.entry(sensor.channel).or_default().extend(inputs.chain(outputs).filter(|(pool, _)| pools[*pool].item_type == item_type));
+        }
+
+        let research = world.resource::<Research>();
+        let tree = world.resource::<ResearchTree>();
+        let needed = research.current.as_deref().and_then(|name| tree.get(name)).map(|tech| {
+            tech.cost.iter().map(|stack| (stack.item_type, research.needed(tree, stack.item_type) as f64)).collect()
+        }).unwrap_or_default();
+
+        let timestep = world.resource::<Time<Fixed>>().timestep();
+        let elapsed = world.resource::<SimTick>().elapsed(world.resource::<Time<Fixed>>());
+        let in_progress = world.resource::<Objectives>().0.iter().filter(|o| o.state == ObjectiveState::InProgress);
+
+        let objectives: Vec<(ItemType, f64)> = in_progress.clone().map(|o| (o.item_type, o.amount.saturating_sub(o.delivered) as f64)).collect();
+        let deadline = in_progress.filter_map(|o| o.deadline).map(|deadline| deadline.saturating_sub(elapsed).div_duration_f64(timestep)).reduce(f64::min);
+
+        let mut model = Self { nodes, pools, needed, objectives, deadline, channels };
+        model.absorb_into_sinks();
+        model
+    }
+
+    fn absorbs(&self, pool: &Pool) -> bool {
+        match pool.consumer {
+            Some((_, Consumer::Sink { .. } | Consumer::Storage { deposit: true })) => true,
+            Some((_, Consumer::Lab)) => self.needed.get(&pool.item_type).is_some_and(|needed| *needed > EPSILON),
+            _ => false,
+        }
+    }
+
+    /// Items per tick going into `pool`
+    fn inflow(&self, pool: &Pool) -> f64 {
+        pool.src.map_or(0.0, |(node, amount)| self.nodes[node].rate * amount)
+    }
+
+    /// Items per tick `pool` could give away if it had them
+    fn outflow(&self, pool: &Pool) -> f64 {
+        if self.absorbs(pool) { return f64::INFINITY; }
+        pool.dest.map_or(0.0, |(node, amount)| self.nodes[node].rate * amount)
+    }
+
+    /// Items per tick `pool` fills up by, negative while it drains
+    fn net(&self, pool: &Pool) -> f64 {
+        if self.absorbs(pool) { 0.0 } else { self.inflow(pool) - self.outflow(pool) }
+    }
+
+    /// Works out every crafter's rate from the buffers that are full or empty. Rates start at their maximum and only go down,
+    /// so this settles on the fastest rates the buffers allow
+    fn solve(&mut self) {
+        for node in self.nodes.iter_mut() {
+            node.rate = node.cap();
+        }
+
+        for _ in 0..MAX_SOLVE_ROUNDS {
+            let mut changed = false;
+            for index in 0..self.nodes.len() {
+                let node = &self.nodes[index];
+                let mut rate = node.cap();
+                for pool in node.inputs.iter().map(|&pool| &self.pools[pool]).filter(|pool| pool.is_empty()) {
+                    let (_, amount) = pool.dest.expect("Input pools know their crafter");
+                    rate = rate.min(self.inflow(pool) / amount);
+                }
+                for pool in node.outputs.iter().map(|&pool| &self.pools[pool]).filter(|pool| pool.is_full()) {
+                    let (_, amount) = pool.src.expect("Output pools know their crafter");
+                    rate = rate.min(self.outflow(pool) / amount);
+                }
+
+                changed |= (rate - node.rate).abs() > EPSILON;
+                self.nodes[index].rate = rate;
+            }
+            if !changed { break; }
+        }
+    }
+
+    /// What the sensors on `channel` add up to, and how fast that changes at the current rates
+    fn channel(&self, channel: Channel) -> (f64, f64) {
+        self.channels.get(&channel).into_iter().flatten()
+            .map(|&(pool, side)| self.pools[pool].reading(side, self.net(&self.pools[pool])))
+            .fold((0.0, 0.0), |(level, rate), (pool_level, pool_rate)| (level + pool_level, rate + pool_rate))
+    }
+
+    /// Readings at which `condition` starts or stops holding, rounding them like the buffers they stand for
+    fn thresholds(condition: &EnableCondition) -> [f64; 2] {
+        [condition.value as f64 - 0.5, condition.value as f64 + 0.5]
+    }
+
+    /// Lets each machine with an enable condition run while it holds, and stops it while it doesn't. A machine right at
+    /// its threshold that would stop holding by running, and start holding again by stopping, runs just fast enough to
+    /// stay there
+    fn enable(&mut self) {
+        for index in 0..self.nodes.len() {
+            let Some(condition) = self.nodes[index].condition else { continue };
+            let holds = |reading: f64| condition.comparison.holds(reading.round().max(0.0) as u64, condition.value);
+            let (reading, _) = self.channel(condition.channel);
+            let threshold = Self::thresholds(&condition).into_iter()
+                .find(|threshold| (reading - threshold).abs() < THRESHOLD_EPSILON && holds(threshold - 0.25) != holds(threshold + 0.25));
+            let Some(threshold) = threshold else {
+                self.nodes[index].enabled = if holds(reading) { 1.0 } else { 0.0 };
+                continue;
+            };
+
+            // How fast the reading goes towards where the condition holds, running at `enabled`
+            let holding_side = if holds(threshold + 0.25) { 1.0 } else { -1.0 };
+            let towards_holding = |model: &mut Self, enabled: f64| {
+                model.nodes[index].enabled = enabled;
+                model.solve();
+                model.channel(condition.channel).1 * holding_side
+            };
+            if towards_holding(self, 1.0) >= -EPSILON {
+                self.nodes[index].enabled = 1.0;
+                continue;
+            }
+            if towards_holding(self, 0.0) <= EPSILON {
+                self.nodes[index].enabled = 0.0;
+                continue;
+            }
+            let (mut stays, mut leaves) = (0.0, 1.0);
+            for _ in 0..THRESHOLD_ROUNDS {
+                let enabled = (stays + leaves) / 2.0;
+                if towards_holding(self, enabled) >= 0.0 { stays = enabled } else { leaves = enabled }
+            }
+            self.nodes[index].enabled = stays;
+        }
+    }
+
+    /// Items per tick labs take in, or sinks deliver, of `item_type`
+    fn consumption(&self, item_type: ItemType, by: fn(Consumer) -> bool) -> f64 {
+        self.pools.iter()
+            .filter(|pool| pool.item_type == item_type && pool.consumer.is_some_and(|(_, consumer)| by(consumer)) && self.absorbs(pool))
+            .map(|pool| self.inflow(pool))
+            .sum()
+    }
+
+    /// Runs for up to `ticks`, stopping early when something needs an actual tick. Returns the ticks it ran for
+    fn run(&mut self, ticks: f64) -> f64 {
+        let mut now = 0.0;
+
+        for _ in 0..MAX_EVENTS {
+            self.finish_due(now);
+            if now >= ticks - EPSILON { break; }
+            self.enable();
+            self.solve();
+
+            let mut step = ticks - now;
+            let mut stops = false;
+            for node in self.nodes.iter().filter(|node| node.is_finishing()) {
+                step = step.min((node.finishes_in - now).max(0.0));
+            }
+            for pool in &self.pools {
+                let net = self.net(pool);
+                if net > EPSILON && !pool.is_full() {
+                    step = step.min((pool.capacity - pool.level) / net);
+                } else if net < -EPSILON && !pool.is_empty() {
+                    step = step.min(pool.level / -net);
+                }
+            }
+            for (item_type, needed) in self.needed.iter().filter(|(_, needed)| **needed > EPSILON) {
+                let rate = self.consumption(*item_type, |consumer| consumer == Consumer::Lab);
+                if rate > EPSILON && needed / rate <= step {
+                    step = needed / rate;
+                    // The last item type the technology needs finishes it
+                    stops = self.needed.values().filter(|needed| **needed > EPSILON).count() == 1;
+                }
+            }
+            for (item_type, left) in &self.objectives {
+                let rate = self.consumption(*item_type, |consumer| matches!(consumer, Consumer::Sink { .. }));
+                if rate > EPSILON && left / rate <= step {
+                    step = left / rate;
+                    stops = true;
+                }
+            }
+            // Enable conditions start or stop holding, and sensors on a coupling start reading a different buffer
+            for condition in self.nodes.iter().filter_map(|node| node.condition) {
+                let (reading, rate) = self.channel(condition.channel);
+                for threshold in Self::thresholds(&condition) {
+                    let distance = threshold - reading;
+                    if distance * rate > 0.0 && distance.abs() > THRESHOLD_EPSILON {
+                        step = step.min(distance / rate);
+                    }
+                }
+            }
+            for pool in self.channels.values().flatten().map(|&(pool, _)| &self.pools[pool]) {
+                let (Some(split), net) = (pool.split(), self.net(pool)) else { continue };
+                let distance = split - pool.level;
+                if distance * net > 0.0 && distance.abs() > THRESHOLD_EPSILON {
+                    step = step.min(distance / net);
+                }
+            }
+            if let Some(deadline) = self.deadline.filter(|deadline| deadline - now <= step) {
+                step = (deadline - now).max(0.0);
+                stops = true;
+            }
+
+            self.advance(step);
+            now += step;
+            if stops { break; }
+        }
+
+        now
+    }
+
+    /// Puts the output of crafts in progress that are due by `now` into their buffers, letting them overflow like craft does
+    fn finish_due(&mut self, now: f64) {
+        for index in 0..self.nodes.len() {
+            let node = &self.nodes[index];
+            if !node.is_finishing() || node.finishes_in > now + EPSILON { continue; }
+
+            let crafts = node.in_progress as f64;
+            for &pool in &node.outputs {
+                let (_, amount) = self.pools[pool].src.expect("Output pools know their crafter");
+                self.pools[pool].level += amount * crafts;
+            }
+            self.nodes[index].finished = true;
+        }
+        self.absorb_into_sinks();
+    }
+
+    /// Whatever's on its way to a sink gets used up right away, like everything after it
+    fn absorb_into_sinks(&mut self) {
+        for pool in self.pools.iter_mut().filter(|pool| matches!(pool.consumer, Some((_, Consumer::Sink { .. } | Consumer::Storage { deposit: true })))) {
+            let absorbed = std::mem::take(&mut pool.level);
+            pool.consumed += absorbed;
+            if matches!(pool.consumer, Some((_, Consumer::Sink { .. }))) && let Some((_, left)) = self.objectives.iter_mut().find(|(item_type, _)| *item_type == pool.item_type) {
+                *left = (*left - absorbed).max(0.0);
+            }
+        }
+    }
+
+    /// Moves everything along at the current rates for `step` ticks
+    fn advance(&mut self, step: f64) {
+        let lab_rates: HashMap<ItemType, f64> = self.needed.keys().map(|item_type| (*item_type, self.consumption(*item_type, |consumer| consumer == Consumer::Lab))).collect();
+        for index in 0..self.pools.len() {
+            let pool = &self.pools[index];
+            let (net, inflow, absorbs) = (self.net(pool), self.inflow(pool), self.absorbs(pool));
+            let moved = if pool.coupling.is_some() { inflow.min(self.outflow(pool)) } else { 0.0 };
+
+            let pool = &mut self.pools[index];
+            pool.level = (pool.level + net * step).clamp(0.0, pool.level.max(pool.capacity));
+            pool.moved += moved * step;
+            if absorbs {
+                pool.consumed += inflow * step;
+            }
+        }
+
+        for node in self.nodes.iter_mut() {
+            node.crafts += node.rate * step;
+            // A machine held back still starts a smaller batch as soon as it can, so it only idles while it can't craft once per recipe
+            node.busy += (node.rate * node.ticks as f64).min(1.0) * step;
+        }
+        for (item_type, needed) in self.needed.iter_mut() {
+            *needed = (*needed - lab_rates[item_type] * step).max(0.0);
+        }
+        for index in 0..self.objectives.len() {
+            let delivered = self.consumption(self.objectives[index].0, |consumer| matches!(consumer, Consumer::Sink { .. })) * step;
+            self.objectives[index].1 = (self.objectives[index].1 - delivered).max(0.0);
+        }
+    }
+
+    /// Writes the model back into `world`, `ticks` after it was captured
+    fn apply(self, world: &mut World, ticks: u64) {
+        world.resource_mut::<SimTick>().0 += ticks;
+
+        for pool in &self.pools {
+            let level = pool.level.round() as u64;
+            let kept = pool.input.map_or(0, |(_, max)| if pool.output.is_some() { level.min(max) } else { level });
+            if let Some((connector, _)) = pool.input && let Some(mut buffer) = world.get_mut::<ItemBuffer>(connector) {
+                buffer.current = kept;
+            }
+            if let Some(connector) = pool.output && let Some(mut buffer) = world.get_mut::<ItemBuffer>(connector) {
+                buffer.current = level - kept;
+            }
+            if let Some(connector) = pool.coupling && let Some(mut flow) = world.get_mut::<CouplingFlow>(connector) {
+                let rate = if ticks > 0 { pool.moved / ticks as f64 } else { 0.0 };
+                *flow = CouplingFlow { moved: rate.round() as u64, rate: rate as f32, backed_up: level > kept };
+            }
+
+            let consumed = pool.consumed.round() as u64;
+            let Some((machine, consumer)) = pool.consumer.filter(|_| consumed > 0) else { continue };
+            if matches!(consumer, Consumer::Sink { .. } | Consumer::Lab) && let Some(mut stats) = world.get_mut::<MachineStats>(machine) {
+                stats.items_consumed += consumed;
+            }
+            match consumer {
+                Consumer::Sink { deposit } => {
+                    world.resource_mut::<Deliveries>().add(pool.item_type, consumed);
+                    world.resource_mut::<Objectives>().credit(pool.item_type, consumed);
+                    if deposit {
+                        world.resource_mut::<Inventory>().add(pool.item_type, consumed);
+                    }
+                },
+                Consumer::Storage { .. } => world.resource_mut::<Inventory>().add(pool.item_type, consumed),
+                Consumer::Lab => *world.resource_mut::<Research>().progress.entry(pool.item_type).or_default() += consumed,
+                Consumer::None => {},
+            }
+        }
+
+        for node in &self.nodes {
+            if let Some(mut stats) = world.get_mut::<MachineStats>(node.machine) {
+                let crafts = node.crafts.round() as u64;
+                let amounts = |pools: &[usize], side: fn(&Pool) -> Option<(usize, f64)>| pools.iter().filter_map(|&pool| side(&self.pools[pool])).map(|(_, amount)| amount).sum::<f64>();
+                let finished = if node.finished { node.in_progress } else { 0 };
+                stats.crafts += crafts + finished;
+                stats.items_consumed += (node.crafts * amounts(&node.inputs, |pool| pool.dest)).round() as u64;
+                stats.items_produced += ((node.crafts + finished as f64) * amounts(&node.outputs, |pool| pool.src)).round() as u64;
+                stats.ticks_working += node.busy.round() as u64 + u64::from(finished > 0) * node.ticks;
+            }
+            // Crafts still in progress carry on, still due on the same tick
+            if node.is_finishing() { continue; }
+            // Starts the machine over, waking it up to look for its next craft
+            world.entity_mut(node.machine).insert(MachineStatus::Idle);
+        }
+    }
+}
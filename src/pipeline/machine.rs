@@ -1,9 +1,10 @@
-use std::{fmt::Debug, ops::{BitOr, BitOrAssign}};
+use std::{fmt::Debug, sync::atomic::{AtomicU64, Ordering}};
 
-use bevy::ecs::{component::Component, entity::UniqueEntityVec};
-use bevy::prelude::*;
+use bevy::ecs::{component::Component, entity::hash_set::EntityHashSet, lifecycle::HookContext, system::SystemParam, world::DeferredWorld};
+use bevy::{prelude::*, utils::Parallel};
+use serde::{Deserialize, Serialize};
 
-use crate::{pipeline::{recipe::Recipe, IoBuffer}, ItemType};
+use crate::{conservation::Conservation, inventory::Inventory, modding::CustomMachine, pipeline::{circuit::{EnableCondition, Signals}, recipe::{ItemStack, Recipe}, wake::{wake_machine, Wakeups}, IoBuffer, SimTick}, research::Research, ItemType};
 
 // #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 // pub enum MachinePushError {
@@ -11,53 +12,189 @@ use crate::{pipeline::{recipe::Recipe, IoBuffer}, ItemType};
 //     InvalidInput,
 // }
 
-// #[derive(Clone, Copy, PartialEq, Eq, Debug)]
-// pub enum MachineBindError {
-//     NoFreeOutputs,
-//     NoFreeInputs,
-//     InputDoesNotExist,
-//     OutputDoesNotExist,
-//     InvalidInput,
-// }
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MachineBindError {
+    NoFreeOutputs,
+    NoFreeInputs,
+    InputDoesNotExist,
+    OutputDoesNotExist,
+    /// The connectors carry different item types
+    InvalidInput,
+    /// Coupling is restricted to neighbours and these two aren't facing each other
+    NotAdjacent,
+}
 
-#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[require(MachineId = MachineId::next())]
 pub enum MachineKind {
     Producer,
     Transformer,
     Combinator,
     Separator,
     Storage,
+    /// Takes in finished goods and credits them towards the objectives
+    Sink,
+    /// Takes in items for the technology being researched
+    Lab,
+    /// Added by a mod, see FactoryAppExt::add_machine_kind. Saved by name, and looked up in the Registry on load
+    #[serde(with = "crate::modding::custom_machine")]
+    Custom(&'static CustomMachine),
 }
 
-#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+impl Debug for MachineKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            MachineKind::Producer => "Producer",
+            MachineKind::Transformer => "Transformer",
+            MachineKind::Combinator => "Combinator",
+            MachineKind::Separator => "Separator",
+            MachineKind::Storage => "Storage",
+            MachineKind::Sink => "Sink",
+            MachineKind::Lab => "Lab",
+            MachineKind::Custom(machine) => machine.name,
+        })
+    }
+}
+
+static NEXT_MACHINE_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// Stays the same when a machine is despawned and restored by undo, unlike its Entity
+pub struct MachineId(pub u64);
+
+impl MachineId {
+    pub fn next() -> Self {
+        Self(NEXT_MACHINE_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Keeps `next` from handing this id out again, for machines restored from a save
+    pub fn reserve(self) {
+        NEXT_MACHINE_ID.fetch_max(self.0 + 1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[require(MachineStats)]
+#[component(on_insert = wake_machine)]
 pub enum MachineStatus {
     Working(Working),
+    /// Can't craft for lack of room in an output
     Full,
+    /// Can't craft for lack of items in an input
     LacksInput,
     CraftsFinished(u64),
     Idle,
+    /// Idle because its EnableCondition doesn't hold
+    ConditionUnmet,
+    /// Idle because it was switched off
+    Disabled,
 }
 
 impl From<MachineStatus> for String {
     fn from(value: MachineStatus) -> Self {
         match value {
-            MachineStatus::Working(Working { ticks_remaining, amount }) => format!("Crafting x{amount}: {ticks_remaining} left"),
+            MachineStatus::Working(Working { amount, .. }) => format!("Crafting x{amount}"),
             MachineStatus::Full => String::from("Full"),
             MachineStatus::Idle => String::from("Idle"),
             MachineStatus::LacksInput => String::from("Waiting for input"),
+            MachineStatus::ConditionUnmet => String::from("Waiting for signal"),
+            MachineStatus::Disabled => String::from("Disabled"),
             _ => String::new(),
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+impl MachineStatus {
+    /// In the middle of a craft, so it'll be woken when that's done rather than by items moving
+    pub fn is_busy(&self) -> bool {
+        matches!(self, MachineStatus::Working(_) | MachineStatus::CraftsFinished(_))
+    }
+
+    /// Can't craft until items arrive or space frees up, as opposed to switched off or waiting for a signal
+    pub fn is_stalled(&self) -> bool {
+        matches!(self, MachineStatus::Idle | MachineStatus::LacksInput | MachineStatus::Full)
+    }
+
+    /// Status as shown to the player on tick `now`, noting when a switched off machine is still finishing its last craft
+    pub fn describe(&self, disabled: bool, now: u64) -> String {
+        match self {
+            MachineStatus::Working(working) if disabled => format!("{}: {} left (stopping)", String::from(*self), working.ticks_remaining(now)),
+            MachineStatus::Working(working) => format!("{}: {} left", String::from(*self), working.ticks_remaining(now)),
+            _ => String::from(*self),
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug, Default)]
+#[component(on_insert = wake_machine, on_remove = wake_machine)]
+/// Switches a machine off. It finishes the craft it's on, then stops taking inputs
+pub struct Disabled;
+
+/// Switches `machine` on or off
+pub fn set_enabled(commands: &mut Commands, machine: Entity, enabled: bool) {
+    if enabled {
+        commands.entity(machine).remove::<Disabled>();
+    } else {
+        commands.entity(machine).insert(Disabled);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Working {
-    ticks_remaining: u64,
-    amount: u64,
+    /// Tick the craft finishes on
+    pub finishes_at: u64,
+    pub amount: u64,
+}
+
+impl Working {
+    /// `amount` crafts of a recipe taking `ticks`, started on tick `now`. A 1 tick recipe finishes on the tick it starts
+    pub fn start(now: u64, ticks: u64, amount: u64) -> Self {
+        Self { finishes_at: now + ticks.max(1) - 1, amount }
+    }
+
+    pub fn ticks_remaining(&self, now: u64) -> u64 {
+        self.finishes_at.saturating_sub(now)
+    }
+
+    /// How far along the craft is on tick `now`, from 0 to 1
+    pub fn progress(&self, recipe: &Recipe, now: u64) -> f32 {
+        1.0 - self.ticks_remaining(now) as f32 / recipe.ticks.max(1) as f32
+    }
+}
+
+#[derive(Component, Clone, Debug, Default, Serialize, Deserialize)]
+/// What was paid to build a machine, refunded when it's deconstructed
+pub struct BuildCost(pub Vec<ItemStack>);
+
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[component(on_add = record_built_at)]
+/// Running totals over a machine's lifetime
+pub struct MachineStats {
+    pub crafts: u64,
+    pub items_consumed: u64,
+    pub items_produced: u64,
+    /// Ticks spent on finished crafts
+    pub ticks_working: u64,
+    /// Tick the machine was built on
+    pub built_at: u64,
+}
+
+impl MachineStats {
+    /// Ticks since the machine was built, as of tick `now`
+    pub fn ticks_total(&self, now: u64) -> u64 {
+        now.saturating_sub(self.built_at)
+    }
+}
+
+fn record_built_at(mut world: DeferredWorld, context: HookContext) {
+    let Some(tick) = world.get_resource::<SimTick>().map(|tick| tick.0) else { return };
+    if let Some(mut stats) = world.get_mut::<MachineStats>(context.entity) {
+        stats.built_at = tick;
+    }
 }
 
 #[derive(Component, Clone, Debug)]
-#[relationship_target(relationship = MachineInput)]
+#[relationship_target(relationship = MachineInput, linked_spawn)]
 /// Connects a Machine to its InputConnectors
 pub struct InputBank(Vec<Entity>);
 
@@ -73,7 +210,7 @@ impl InputBank {
 pub struct MachineInput(pub Entity);
 
 #[derive(Component, Clone, Debug)]
-#[relationship_target(relationship = MachineOutput)]
+#[relationship_target(relationship = MachineOutput, linked_spawn)]
 /// Connects a Machine to its OutputConnectors
 pub struct OutputBank(Vec<Entity>);
 
@@ -97,21 +234,94 @@ pub struct MachineCoupling {
 #[derive(Component, Clone, Debug)]
 pub struct BufferType(pub ItemType);
 
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+/// Index of a connector among its machine's inputs or outputs, the same as the recipe stack its buffer holds
+pub struct BufferSlot(pub usize);
+
 #[derive(Component, Clone, Debug)]
 #[relationship_target(relationship = OutputPort)]
 /// Connects an OutputConnector to an InputConnector
 pub struct InputPort(Entity);
 
+impl InputPort {
+    pub fn get(&self) -> Entity {
+        self.0
+    }
+}
+
 #[derive(Component, Clone, Debug)]
 #[relationship(relationship_target = InputPort)]
+#[require(CouplingFlow)]
 /// Connects an OutputConnector to an InputConnector
 pub struct OutputPort(pub Entity);
 
+#[derive(Component, Clone, Copy, Debug, Default)]
+/// How many items went through a coupling, updated by push_outputs every tick
+pub struct CouplingFlow {
+    pub moved: u64,
+    /// Smoothed items per tick
+    pub rate: f32,
+    /// Items were left behind in the output buffer after pushing
+    pub backed_up: bool,
+}
+
+impl CouplingFlow {
+    pub fn record(&mut self, moved: u64, backed_up: bool) {
+        self.moved = moved;
+        self.rate = self.rate * 0.9 + moved as f32 * 0.1;
+        self.backed_up = backed_up;
+    }
+
+    pub fn is_idle(&self) -> bool {
+        !self.backed_up && self.rate < 0.01
+    }
+}
+
+#[derive(SystemParam)]
+/// Follows couplings from a machine to the machines on the other end
+pub struct CouplingGraph<'w, 's> {
+    banks: Query<'w, 's, (Option<&'static InputBank>, Option<&'static OutputBank>)>,
+    input_ports: Query<'w, 's, &'static InputPort>,
+    output_ports: Query<'w, 's, &'static OutputPort>,
+    input_machines: Query<'w, 's, &'static MachineInput>,
+    output_machines: Query<'w, 's, &'static MachineOutput>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Both ends of a coupling, as connectors and as the machines they belong to
+pub struct Coupling {
+    pub src: Entity,
+    pub output: Entity,
+    pub dest: Entity,
+    pub input: Entity,
+}
+
+impl CouplingGraph<'_, '_> {
+    /// Couplings feeding into `machine`
+    pub fn upstream(&self, machine: Entity) -> Vec<Coupling> {
+        let Ok((Some(input_bank), _)) = self.banks.get(machine) else { return Vec::new() };
+        input_bank.iter()
+            .filter_map(|input| self.input_ports.get(input).ok().map(|port| (port.get(), input)))
+            .filter_map(|(output, input)| self.output_machines.get(output).ok().map(|MachineOutput(src)| Coupling { src: *src, output, dest: machine, input }))
+            .collect()
+    }
+
+    /// Couplings `machine` feeds into
+    pub fn downstream(&self, machine: Entity) -> Vec<Coupling> {
+        let Ok((_, Some(output_bank))) = self.banks.get(machine) else { return Vec::new() };
+        output_bank.iter()
+            .filter_map(|output| self.output_ports.get(output).ok().map(|OutputPort(input)| (output, *input)))
+            .filter_map(|(output, input)| self.input_machines.get(input).ok().map(|MachineInput(dest)| Coupling { src: machine, output, dest: *dest, input }))
+            .collect()
+    }
+}
+
 #[derive(Bundle, Clone, Debug)]
 /// Connects an OutputBank to an InputConnector
 pub struct OutputConnector {
     pub machine: MachineOutput,
     pub buffer_type: BufferType,
+    pub buffer: ItemBuffer,
 }
 
 #[derive(Bundle, Clone, Debug)]
@@ -119,6 +329,7 @@ pub struct OutputConnector {
 pub struct InputConnector {
     pub machine: MachineInput,
     pub buffer_type: BufferType,
+    pub buffer: ItemBuffer,
 }
 
 impl From<ItemType> for BufferType {
@@ -127,15 +338,81 @@ impl From<ItemType> for BufferType {
     }
 }
 
-#[derive(Component, Clone, Debug)]
-pub struct InputBuffers(pub Vec<IoBuffer>);
+#[derive(SystemParam)]
+/// Reads the buffers on machines' connectors, in slot order
+pub struct MachineBuffers<'w, 's> {
+    banks: Query<'w, 's, (Option<&'static InputBank>, Option<&'static OutputBank>)>,
+    connectors: Query<'w, 's, (&'static BufferType, &'static BufferSlot, Ref<'static, ItemBuffer>)>,
+}
 
-#[derive(Component, Clone, Debug)]
-pub struct OutputBuffers(pub Vec<IoBuffer>);
+impl MachineBuffers<'_, '_> {
+    pub fn inputs(&self, machine: Entity) -> Vec<IoBuffer> {
+        let Ok((Some(bank), _)) = self.banks.get(machine) else { return Vec::new() };
+        self.collect(bank.get())
+    }
 
-#[derive(Component, Clone, Copy, Debug)]
+    pub fn outputs(&self, machine: Entity) -> Vec<IoBuffer> {
+        let Ok((_, Some(bank))) = self.banks.get(machine) else { return Vec::new() };
+        self.collect(bank.get())
+    }
+
+    /// Whether any of `machine`'s input and output buffers changed since the system last ran
+    pub fn changed(&self, machine: Entity) -> (bool, bool) {
+        let changed = |bank: Option<&Vec<Entity>>| self.connectors.iter_many(bank.into_iter().flatten()).any(|(_, _, buffer)| buffer.is_changed());
+        let Ok((inputs, outputs)) = self.banks.get(machine) else { return (false, false) };
+        (changed(inputs.map(InputBank::get)), changed(outputs.map(OutputBank::get)))
+    }
+
+    fn collect(&self, connectors: &[Entity]) -> Vec<IoBuffer> {
+        let mut buffers: Vec<(usize, IoBuffer)> = self.connectors.iter_many(connectors)
+            .map(|(BufferType(item_type), BufferSlot(slot), buffer)| (*slot, IoBuffer { buffer: *buffer, item_type: *item_type }))
+            .collect();
+        buffers.sort_by_key(|(slot, _)| *slot);
+        buffers.into_iter().map(|(_, buffer)| buffer).collect()
+    }
+}
+
+/// `machine`'s input buffers in slot order, straight from the World
+pub fn input_buffers(world: &World, machine: Entity) -> Vec<IoBuffer> {
+    connector_buffers(world, world.get::<InputBank>(machine).map(|bank| bank.get().as_slice()).unwrap_or_default())
+}
+
+/// `machine`'s output buffers in slot order, straight from the World
+pub fn output_buffers(world: &World, machine: Entity) -> Vec<IoBuffer> {
+    connector_buffers(world, world.get::<OutputBank>(machine).map(|bank| bank.get().as_slice()).unwrap_or_default())
+}
+
+fn connector_buffers(world: &World, connectors: &[Entity]) -> Vec<IoBuffer> {
+    let mut buffers: Vec<(usize, IoBuffer)> = connectors.iter()
+        .filter_map(|connector| Some((world.get::<BufferSlot>(*connector)?.0, IoBuffer { buffer: *world.get::<ItemBuffer>(*connector)?, item_type: world.get::<BufferType>(*connector)?.0 })))
+        .collect();
+    buffers.sort_by_key(|(slot, _)| *slot);
+    buffers.into_iter().map(|(_, buffer)| buffer).collect()
+}
+
+#[derive(Component, Clone, Copy, Debug, Serialize, Deserialize)]
+#[component(on_insert = resize_for_mult, on_remove = resize_for_mult)]
 pub struct Mult(pub u64);
 
+/// Buffers have to fit a whole batch, so they grow along with the Mult
+fn resize_for_mult(mut world: DeferredWorld, context: HookContext) {
+    let machine = context.entity;
+    wake_machine(world.reborrow(), context);
+    world.commands().queue(move |world: &mut World| fit_buffers(world, machine));
+}
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[component(on_insert = resize_for_capacity, on_remove = resize_for_capacity)]
+/// Items an InputConnector's or OutputConnector's buffer holds, in place of the recipe's buffer_size
+pub struct Capacity(pub u64);
+
+fn resize_for_capacity(mut world: DeferredWorld, context: HookContext) {
+    let machine = world.get::<MachineInput>(context.entity).map(|input| input.0).or_else(|| world.get::<MachineOutput>(context.entity).map(|output| output.0));
+    if let Some(machine) = machine {
+        world.commands().queue(move |world: &mut World| fit_buffers(world, machine));
+    }
+}
+
 impl InputBank {
     pub fn new() -> Self {
         Self(Vec::new())
@@ -146,6 +423,12 @@ impl InputBank {
     }
 }
 
+impl Default for InputBank {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl OutputBank {
     pub fn new() -> Self {
         Self(Vec::new())
@@ -156,13 +439,17 @@ impl OutputBank {
     }
 }
 
+impl Default for OutputBank {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Bundle, Clone, Debug)]
 pub struct Machine {
     pub kind: MachineKind,
     pub input_bank: InputBank,
-    pub input_buffers: InputBuffers,
     pub output_bank: OutputBank,
-    pub output_buffers: OutputBuffers,
     pub recipe: Recipe,
     pub status: MachineStatus,
 }
@@ -170,11 +457,13 @@ pub struct Machine {
 #[derive(Bundle, Clone, Debug)]
 pub struct Producer {
     pub output_bank: OutputBank,
-    pub output_buffers: OutputBuffers,
     pub recipe: Recipe,
     pub status: MachineStatus,
 }
 
+#[derive(Component, Clone, Debug)]
+pub struct NameText(pub Entity);
+
 #[derive(Component, Clone, Debug)]
 pub struct InputBufferText(pub Entity);
 
@@ -184,108 +473,241 @@ pub struct OutputBufferText(pub Entity);
 #[derive(Component, Clone, Debug)]
 pub struct StatusText(pub Entity);
 
-pub fn tick_crafts(mut machine_query: Query<(&mut MachineStatus)>) {
-    for entity in &mut machine_query {
-        let (mut status) = entity;
-        match *status {
-            MachineStatus::Working(Working { ref mut ticks_remaining, amount}) => {
-                *ticks_remaining -= 1;
-
-                if *ticks_remaining == 0 {
-                    *status = MachineStatus::CraftsFinished(amount);
-                }
-            },
-            _ => {},
-        }
+/// Finishes the crafts due this tick, rather than counting down every working machine
+pub fn tick_crafts(tick: Res<SimTick>, mut wakeups: ResMut<Wakeups>, mut machine_query: Query<&mut MachineStatus>) {
+    while let Some(machine) = wakeups.next_due(tick.0) {
+        let Ok(mut status) = machine_query.get_mut(machine) else { continue };
+        // Timers of crafts that were since cancelled or replaced are left in the queue, skip those
+        let MachineStatus::Working(working) = *status else { continue };
+        if working.finishes_at > tick.0 { continue; }
+
+        *status = MachineStatus::CraftsFinished(working.amount);
+        wakeups.finish(machine);
     }
 }
 
-pub fn craft(mut machine_query: Query<(&mut OutputBuffers, &mut MachineStatus, &Recipe)>) {
-    for (mut buffers, mut status, recipe, num_crafts) in machine_query.iter_mut().filter_map(|(buffers, status, recipe)| {
-        if let MachineStatus::CraftsFinished(num_crafts) = *status {
-            Some((buffers, status, recipe, num_crafts))
-        } else {
-            None
-        }
-    }) {
-        for item_stack in recipe.outputs.iter().filter_map(|o| *o) {
-            let buffer = buffers.0.iter_mut().find(|b| b.item_type == item_stack.item_type).expect(format!("No buffer for recipe output: {:?}", item_stack.item_type).as_ref());
-            // Buffers were checked for space during ready phase, just let it overflow here
-            buffer.buffer.current += item_stack.amount * num_crafts;
-            println!("Crafted {:?} x{}", item_stack.item_type, item_stack.amount * num_crafts);
+/// Collects finished crafts into the output buffers, then wakes the machines to start the next one.
+/// Only the machines whose craft finished are looked at, in parallel, and what they made is written after
+pub fn craft(mut wakeups: ResMut<Wakeups>, mut machine_query: Query<(Entity, &OutputBank, &mut MachineStatus, &mut MachineStats, &Recipe)>, slot_query: Query<&BufferSlot>, mut buffer_query: Query<&mut ItemBuffer>, mut crafted: Local<Parallel<Vec<(Entity, u64, u64)>>>, mut produced: Local<Parallel<Vec<(Entity, u64)>>>) {
+    let finished = wakeups.take_finished();
+    if finished.is_empty() { return; }
+
+    machine_query.par_iter_many(&finished).for_each(|(machine, outputs, status, _, recipe)| {
+        let MachineStatus::CraftsFinished(num_crafts) = *status else { return };
+
+        let mut produced = produced.borrow_local_mut();
+        let mut items_produced = 0;
+        for output in outputs.iter() {
+            let Ok(BufferSlot(slot)) = slot_query.get(output) else { continue };
+            let Some(item_stack) = recipe.outputs.iter().flatten().nth(*slot) else { continue };
+            produced.push((output, item_stack.amount * num_crafts));
+            items_produced += item_stack.amount * num_crafts;
         }
+        crafted.borrow_local_mut().push((machine, num_crafts, items_produced));
+    });
+
+    for (machine, num_crafts, items_produced) in crafted.drain() {
+        let Ok((_, _, mut status, mut stats, recipe)) = machine_query.get_mut(machine) else { continue };
+        stats.items_produced += items_produced;
+        stats.crafts += num_crafts;
+        stats.ticks_working += recipe.ticks.max(1);
         *status = MachineStatus::Idle;
     }
+    for (output, amount) in produced.drain() {
+        // Buffers were checked for space during ready phase, just let it overflow here
+        if let Ok(mut buffer) = buffer_query.get_mut(output) {
+            buffer.current += amount;
+        }
+    }
+    for machine in finished {
+        wakeups.wake(machine);
+    }
 }
 
-pub fn ready_craft(mut machine_query: Query<(Option<&mut InputBuffers>, &OutputBuffers, &mut MachineStatus, &Recipe, Option<&Mult>)>, ) {
-    for (mut inputs, outputs, mut status, recipe, mult) in &mut machine_query.iter_mut().filter(|(_, _, status, _, _)| **status == MachineStatus::Idle) {
+type ReadyMachine = (Entity, Option<&'static InputBank>, &'static OutputBank, &'static mut MachineStatus, &'static mut MachineStats, &'static Recipe, Option<&'static Mult>, Option<&'static EnableCondition>, Has<Disabled>);
+
+/// Starts crafts on the machines that were woken up. The rest are either working or waiting on something to change.
+/// The awake machines decide in parallel from their own buffers, which only they take from, then their statuses
+/// and the inputs they took are written
+#[allow(clippy::too_many_arguments)]
+pub fn ready_craft(mut machine_query: Query<ReadyMachine>, mut buffer_query: Query<(&BufferSlot, &mut ItemBuffer)>, conditioned_query: Query<Entity, With<EnableCondition>>, signals: Res<Signals>, tick: Res<SimTick>, mut wakeups: ResMut<Wakeups>, mut decided: Local<Parallel<Vec<(Entity, MachineStatus, u64)>>>, mut consumed: Local<Parallel<Vec<(Entity, u64)>>>) {
+    let mut awake = wakeups.take_awake();
+    // Signals can change every tick, so machines with an EnableCondition are always checked
+    awake.extend(&conditioned_query);
+    if awake.is_empty() { return; }
+
+    let buffers = buffer_query.as_readonly();
+    machine_query.par_iter_many(&awake).for_each(|(machine, inputs, outputs, status, _, recipe, mult, condition, disabled)| {
+        if !matches!(*status, MachineStatus::Idle | MachineStatus::LacksInput | MachineStatus::Full | MachineStatus::ConditionUnmet | MachineStatus::Disabled) { return; }
+        let decide = |next: MachineStatus, items_consumed: u64| {
+            if next != *status { decided.borrow_local_mut().push((machine, next, items_consumed)); }
+        };
+        if disabled {
+            decide(MachineStatus::Disabled, 0);
+            return;
+        }
+        if condition.is_some_and(|condition| !condition.holds(&signals)) {
+            decide(MachineStatus::ConditionUnmet, 0);
+            return;
+        }
+
         let mut possible_crafts = mult.unwrap_or(&Mult(1)).0;
+        // What it waits on when it can't craft, the inputs first
+        let mut waiting = MachineStatus::Idle;
+        let inputs = inputs.map(InputBank::get).map(Vec::as_slice).unwrap_or_default();
+
+        // Every connector holds the items for its own recipe stack, even when two stacks are of the same item type
+        for (BufferSlot(slot), buffer) in buffers.iter_many(inputs) {
+            let Some(input) = recipe.inputs.iter().flatten().nth(*slot) else { continue };
+            possible_crafts = possible_crafts.min(buffer.current / input.amount);
+            if possible_crafts == 0 {
+                waiting = MachineStatus::LacksInput;
+                break;
+            }
+        }
 
-        if let Some(inputs) = &mut inputs {
-            for input in recipe.inputs.iter().filter_map(|i| *i) {
-                let buffered = inputs.0.iter().fold(0, |acc, port| {
-                    if port.item_type == input.item_type {
-                        acc + port.buffer.current
-                    } else {
-                        acc
-                    }
-                });
-
-                possible_crafts = possible_crafts.min(buffered / input.amount);
-                if possible_crafts == 0 { break; }
+        for (BufferSlot(slot), buffer) in buffers.iter_many(outputs.iter()) {
+            if possible_crafts == 0 { break; }
+            let Some(output) = recipe.outputs.iter().flatten().nth(*slot) else { continue };
+            possible_crafts = possible_crafts.min(buffer.remaining() / output.amount);
+            if possible_crafts == 0 {
+                waiting = MachineStatus::Full;
+                break;
             }
         }
 
-        for output in recipe.outputs.iter().filter_map(|o: &Option<super::recipe::ItemStack>| *o) {
-            let bufferable = outputs.0.iter().fold(0, |acc, port| {
-                if port.item_type == output.item_type {
-                    acc + port.buffer.remaining()
-                } else {
-                    acc
-                }
-            });
+        if possible_crafts == 0 {
+            decide(waiting, 0);
+            return;
+        }
 
-            possible_crafts = possible_crafts.min(bufferable / output.amount);
-            if possible_crafts == 0 { break; }
+        let mut items_consumed = 0;
+        let mut consumed = consumed.borrow_local_mut();
+        for input in inputs {
+            let Ok((BufferSlot(slot), _)) = buffers.get(*input) else { continue };
+            let Some(stack) = recipe.inputs.iter().flatten().nth(*slot) else { continue };
+            consumed.push((*input, stack.amount * possible_crafts));
+            items_consumed += stack.amount * possible_crafts;
+        }
+        decide(MachineStatus::Working(Working::start(tick.0, recipe.ticks, possible_crafts)), items_consumed);
+    });
+
+    for (machine, next, items_consumed) in decided.drain() {
+        let Ok((_, _, _, mut status, mut stats, ..)) = machine_query.get_mut(machine) else { continue };
+        *status = next;
+        stats.items_consumed += items_consumed;
+        if let MachineStatus::Working(working) = next {
+            wakeups.wake_at(machine, working.finishes_at);
         }
+    }
+    for (input, amount) in consumed.drain() {
+        if let Ok((_, mut buffer)) = buffer_query.get_mut(input) {
+            buffer.current -= amount;
+        }
+    }
+}
 
-        if let Some(inputs) = &mut inputs {
-            for input in recipe.inputs.iter().filter_map(|o| *o) {
-                let mut taken = input.amount * possible_crafts;
+/// Output connectors that were just coupled or whose buffer changed
+type ChangedCoupling = (With<OutputPort>, Or<(Changed<ItemBuffer>, Changed<OutputPort>)>);
+
+/// The couplings' buffers, first which changed on either end then the buffers themselves
+type CouplingBuffers<'w, 's> = ParamSet<'w, 's, (Query<'static, 'static, Entity, ChangedCoupling>, Query<'static, 'static, &'static InputPort, Changed<ItemBuffer>>, Query<'static, 'static, &'static mut ItemBuffer>)>;
+
+/// Moves items across the couplings that could have something to move, straight from the output connector's buffer into the input connector's.
+/// That's the ones whose buffer on either end changed since last tick, plus the ones still flowing or backed up so their flow keeps up to date.
+/// Machines on either end wake up if they were waiting, for the input that arrived or the output space that freed up
+pub fn push_outputs(mut coupling_query: Query<(&MachineOutput, &OutputPort, &mut CouplingFlow)>, mut buffers: CouplingBuffers, input_query: Query<&MachineInput>, status_query: Query<&MachineStatus>, mut wakeups: ResMut<Wakeups>, mut flowing: Local<EntityHashSet>) {
+    let mut couplings = std::mem::take(&mut *flowing);
+    couplings.extend(&buffers.p0());
+    couplings.extend(buffers.p1().iter().map(InputPort::get));
+
+    let mut buffer_query = buffers.p2();
+    for output in couplings {
+        let Ok((MachineOutput(src), OutputPort(input), mut flow)) = coupling_query.get_mut(output) else { continue };
+        if buffer_query.get(output).is_ok_and(|buf| buf.current == 0) {
+            flow.record(0, false);
+            if !flow.is_idle() { flowing.insert(output); }
+            continue;
+        }
 
-                for input in inputs.0.iter_mut().filter(|i| i.item_type == input.item_type) {
-                    let takeable = taken.min(input.buffer.current);
-                    input.buffer.current -= takeable;
-                    taken -= takeable;
-                    if taken == 0 { break; }
-                }
-            }
+        let Ok(MachineInput(dest)) = input_query.get(*input) else { continue };
+        let Ok([mut buf, mut dest_buf]) = buffer_query.get_many_mut([output, *input]) else { continue };
+        let pushable = dest_buf.remaining().min(buf.current);
+        if pushable > 0 {
+            buf.current -= pushable;
+            dest_buf.current += pushable;
         }
+        flow.record(pushable, buf.current > 0);
+        if !flow.is_idle() { flowing.insert(output); }
 
-        if possible_crafts > 0 {
-            *status = MachineStatus::Working(Working { ticks_remaining: recipe.ticks, amount: possible_crafts });
+        if pushable == 0 { continue; }
+        for machine in [*src, *dest] {
+            if status_query.get(machine).is_ok_and(|status| !status.is_busy()) {
+                wakeups.wake(machine);
+            }
         }
     }
 }
 
-pub fn push_outputs(mut src_query: Query<(&mut OutputBuffers, &OutputBank)>, coupling_query: Query<(&MachineInput, &BufferType)>, mut dest_query: Query<&mut InputBuffers>) {
-    for (mut output_buf, output_bank) in &mut src_query.iter_mut().filter(|(_, output_bank)| output_bank.len() > 0) {
-        let couplings: Vec<(&MachineInput, &BufferType)> = output_bank.iter().filter_map(|entity| coupling_query.get(entity).ok()).collect();
-        for buf in output_buf.0.iter_mut().filter(|buf| buf.buffer.current > 0) {
-            let Some(coupling) = couplings.iter().find(|(_, BufferType(item_type))| *item_type == buf.item_type) else { continue };
-            let mut dest_buf = dest_query.get_mut(coupling.0.0).unwrap();
-            let Some(dest_buf) = dest_buf.0.iter_mut().find(|b| b.item_type == buf.item_type) else { continue };
-            let pushable = dest_buf.buffer.remaining().min(buf.buffer.current);
-            
-            buf.buffer.current -= pushable;
-            dest_buf.buffer.current += pushable;
+/// Items a buffer holds unless its recipe or port says otherwise
+pub const DEFAULT_BUFFER_SIZE: u64 = 50;
+
+/// Sizes every one of `machine`'s buffers to its port's Capacity, or the recipe's buffer_size without one, raised to the
+/// size research has upgraded buffers to and to at least one batch of the recipe times the Mult. Items that don't fit anymore go to the Inventory,
+/// recorded in the Conservation ledger when it's checked
+pub fn fit_buffers(world: &mut World, machine: Entity) {
+    let Ok(entity) = world.get_entity(machine) else { return };
+    let Some(recipe) = entity.get::<Recipe>().copied() else { return };
+    let mult = entity.get::<Mult>().map_or(1, |mult| mult.0);
+    let upgraded = world.get_resource::<Research>().map_or(0, Research::buffer_size);
+
+    let inputs = entity.get::<InputBank>().map(|bank| bank.get().clone()).unwrap_or_default();
+    let outputs = entity.get::<OutputBank>().map(|bank| bank.get().clone()).unwrap_or_default();
+
+    let mut overflow = Vec::new();
+    for (output, connectors, stacks) in [(false, inputs, recipe.inputs), (true, outputs, recipe.outputs)] {
+        for connector in connectors {
+            let Ok(mut connector) = world.get_entity_mut(connector) else { continue };
+            let (Some(BufferSlot(slot)), Some(BufferType(item_type))) = (connector.get::<BufferSlot>().copied(), connector.get::<BufferType>().cloned()) else { continue };
+            let configured = connector.get::<Capacity>().map_or(recipe.buffer_size, |capacity| capacity.0);
+            let batch = stacks.iter().flatten().nth(slot).map_or(0, |stack| stack.amount);
+            let Some(mut buffer) = connector.get_mut::<ItemBuffer>() else { continue };
+
+            buffer.max = configured.max(upgraded).max(batch * mult);
+            if buffer.current > buffer.max {
+                overflow.push((output, slot, ItemStack::new(item_type, buffer.current - buffer.max)));
+                buffer.current = buffer.max;
+            }
         }
     }
+    if let Some(mut conservation) = world.get_resource_mut::<Conservation>() {
+        for (output, slot, stack) in overflow.iter().copied() {
+            conservation.record_overflow(machine, output, slot, stack);
+        }
+    }
+    if let Some(mut inventory) = world.get_resource_mut::<Inventory>() {
+        inventory.add_stacks(overflow.into_iter().map(|(_, _, stack)| stack));
+    }
+    // Room to push into or craft out to may have opened up
+    if let Some(mut wakeups) = world.get_resource_mut::<Wakeups>() {
+        wakeups.wake(machine);
+    }
 }
-const DEFAULT_BUFFER_SIZE: u64 = 50;
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Resizes every buffer once research has upgraded them
+pub fn upgrade_buffers(world: &mut World, mut upgraded: Local<u64>) {
+    let size = world.resource::<Research>().buffer_size();
+    if size == *upgraded { return; }
+    *upgraded = size;
+
+    let machines: Vec<Entity> = world.query_filtered::<Entity, With<Recipe>>().iter(world).collect();
+    for machine in machines {
+        fit_buffers(world, machine);
+    }
+}
+
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+/// Items held at an InputConnector or OutputConnector
 pub struct ItemBuffer {
     pub current: u64,
     pub max: u64,
@@ -303,4 +725,10 @@ impl ItemBuffer {
     pub fn remaining(&self) -> u64 {
         self.max - self.current
     }
+}
+
+impl Default for ItemBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
\ No newline at end of file
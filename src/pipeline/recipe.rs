@@ -1,15 +1,18 @@
-use crate::{pipeline::{machine::MachineKind}, ItemType};
+use crate::{pipeline::{machine::{MachineKind, DEFAULT_BUFFER_SIZE}}, ItemType};
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Recipe {
     pub machine_kind: MachineKind,
     pub ticks: u64,
     pub inputs: [Option<ItemStack>; 4],
     pub outputs: [Option<ItemStack>; 4],
+    /// Items each of the machine's buffers holds, unless its port has a Capacity of its own
+    pub buffer_size: u64,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct ItemStack {
     pub item_type: ItemType,
     pub amount: u64,
@@ -23,19 +26,57 @@ impl ItemStack {
 
 impl Recipe {
     pub fn producer_recipe(output: ItemStack, ticks: u64) -> Self {
-        Self { machine_kind: MachineKind::Producer, ticks, inputs: [None; 4], outputs: [Some(output), None, None, None] }
+        Self { machine_kind: MachineKind::Producer, ticks, inputs: [None; 4], outputs: [Some(output), None, None, None], buffer_size: DEFAULT_BUFFER_SIZE }
     }
 
     pub fn transformer_recipe(input: ItemStack, output: ItemStack, ticks: u64) -> Self {
-        Self { machine_kind: MachineKind::Transformer, ticks, inputs: [Some(input), None, None, None], outputs: [Some(output), None, None, None] }
+        Self { machine_kind: MachineKind::Transformer, ticks, inputs: [Some(input), None, None, None], outputs: [Some(output), None, None, None], buffer_size: DEFAULT_BUFFER_SIZE }
     }
 
     pub fn combinator_recipe(inputs: (ItemStack, ItemStack), output: ItemStack, ticks: u64) -> Self {
-        Self { machine_kind: MachineKind::Combinator, ticks, inputs: [Some(inputs.0), Some(inputs.1), None, None], outputs: [Some(output), None, None, None] }
+        Self { machine_kind: MachineKind::Combinator, ticks, inputs: [Some(inputs.0), Some(inputs.1), None, None], outputs: [Some(output), None, None, None], buffer_size: DEFAULT_BUFFER_SIZE }
     }
 
     pub fn separator_recipe(input: ItemStack, outputs: (ItemStack, ItemStack), ticks: u64) -> Self {
-        Self { machine_kind: MachineKind::Separator, ticks, inputs: [Some(input), None, None, None], outputs: [Some(outputs.0), Some(outputs.1), None, None] }
+        Self { machine_kind: MachineKind::Separator, ticks, inputs: [Some(input), None, None, None], outputs: [Some(outputs.0), Some(outputs.1), None, None], buffer_size: DEFAULT_BUFFER_SIZE }
+    }
+
+    /// Sinks only have an input, they consume whatever arrives every tick
+    pub fn sink_recipe(input: ItemType) -> Self {
+        Self { machine_kind: MachineKind::Sink, ticks: 1, inputs: [Some(ItemStack::new(input, 1)), None, None, None], outputs: [None; 4], buffer_size: DEFAULT_BUFFER_SIZE }
+    }
+
+    /// Storages hold onto a single item type, for reading with sensors or depositing into the Inventory
+    pub fn storage_recipe(input: ItemType) -> Self {
+        Self { machine_kind: MachineKind::Storage, ticks: 1, inputs: [Some(ItemStack::new(input, 1)), None, None, None], outputs: [None; 4], buffer_size: DEFAULT_BUFFER_SIZE }
+    }
+
+    /// Labs take in one item type for research
+    pub fn lab_recipe(input: ItemType) -> Self {
+        Self { machine_kind: MachineKind::Lab, ticks: 1, inputs: [Some(ItemStack::new(input, 1)), None, None, None], outputs: [None; 4], buffer_size: DEFAULT_BUFFER_SIZE }
+    }
+
+    /// Any machine kind with up to 4 inputs and outputs, mainly for mods adding their own
+    pub fn new(machine_kind: MachineKind, inputs: &[ItemStack], outputs: &[ItemStack], ticks: u64) -> Self {
+        assert!(inputs.len() <= 4 && outputs.len() <= 4, "recipes have at most 4 inputs and 4 outputs");
+        let mut recipe = Self { machine_kind, ticks, inputs: [None; 4], outputs: [None; 4], buffer_size: DEFAULT_BUFFER_SIZE };
+        for (slot, stack) in recipe.inputs.iter_mut().zip(inputs) { *slot = Some(*stack); }
+        for (slot, stack) in recipe.outputs.iter_mut().zip(outputs) { *slot = Some(*stack); }
+        recipe
+    }
+
+    /// Same recipe with buffers holding `buffer_size` items, machines still fit at least a batch whatever it's set to
+    pub fn with_buffer_size(mut self, buffer_size: u64) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Machine kind and what it makes, e.g. "Combinator: Transformer", or what it takes for a Sink, Storage, Lab or anything else without outputs
+    pub fn name(&self) -> String {
+        let takes_only = matches!(self.machine_kind, MachineKind::Sink | MachineKind::Storage | MachineKind::Lab) || self.outputs.iter().all(Option::is_none);
+        let stacks = if takes_only { &self.inputs } else { &self.outputs };
+        let items: Vec<String> = stacks.iter().filter_map(|s| s.map(|s| format!("{:?}", s.item_type))).collect();
+        format!("{:?}: {}", self.machine_kind, items.join(", "))
     }
 }
 
@@ -54,6 +95,17 @@ impl Recipes {
             Recipe::combinator_recipe((ItemStack::new(ItemType::Input, 5), ItemStack::new(ItemType::Output, 5)), ItemStack::new(ItemType::Transformer, 1), 20),
             Recipe::combinator_recipe((ItemStack::new(ItemType::Transformer, 1), ItemStack::new(ItemType::Input, 5)), ItemStack::new(ItemType::Combinator, 1), 60),
             Recipe::combinator_recipe((ItemStack::new(ItemType::Transformer, 1), ItemStack::new(ItemType::Output, 5)), ItemStack::new(ItemType::Separator, 1), 60),
+            Recipe::storage_recipe(ItemType::Input),
+            Recipe::storage_recipe(ItemType::Output),
+            Recipe::storage_recipe(ItemType::Transformer),
+            Recipe::sink_recipe(ItemType::Producer),
+            Recipe::sink_recipe(ItemType::Transformer),
+            Recipe::sink_recipe(ItemType::Combinator),
+            Recipe::sink_recipe(ItemType::Separator),
+            Recipe::sink_recipe(ItemType::Storage),
+            Recipe::lab_recipe(ItemType::Transformer),
+            Recipe::lab_recipe(ItemType::Combinator),
+            Recipe::lab_recipe(ItemType::Storage),
         ] }
     }
 
@@ -71,6 +123,10 @@ impl Recipes {
             && e.outputs[0].map(|inner| inner.item_type) == Some(output) { Some(*e) } else { None }})
     }
 
+    pub fn get_sink(&self, input: ItemType) -> Option<Recipe> {
+        self.inner.iter().find(|e| e.machine_kind == MachineKind::Sink && e.inputs[0].map(|inner| inner.item_type) == Some(input)).copied()
+    }
+
     pub fn get_separator(&self, outputs: (ItemType, ItemType)) -> Option<Recipe> {
         self.inner.iter().find_map(|e| {
             if e.machine_kind == MachineKind::Combinator
@@ -0,0 +1,260 @@
+use std::{cmp::Reverse, collections::{BinaryHeap, VecDeque}};
+
+use bevy::{ecs::{entity::{hash_map::EntityHashMap, hash_set::EntityHashSet}, system::SystemParam}, prelude::*};
+
+use crate::{pipeline::{machine::{BufferSlot, BufferType, CouplingGraph, InputBank, InputPort, ItemBuffer, MachineStatus, OutputBank, OutputPort}, recipe::{ItemStack, Recipe}, SimTick}, ItemType};
+
+pub const VALIDATE_KEY: KeyCode = KeyCode::KeyV;
+/// Ticks machines have to be waiting on each other before it's called a deadlock, 30 s at the default tick rate
+pub const DEADLOCK_TICKS: u64 = 300;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Something about the way machines are coupled that keeps part of the factory from running
+pub enum GraphIssue {
+    /// No machine can ever supply `machine`'s input, either nothing is coupled to it or `via` is starved itself
+    UnsuppliedInput { machine: Entity, item_type: ItemType, via: Option<Entity> },
+    /// No machine takes `machine`'s output for good, either nothing is coupled to it or `via` stalls itself, so it fills up and stalls
+    UnconsumedOutput { machine: Entity, item_type: ItemType, via: Option<Entity> },
+    /// Machines feeding each other in a loop, which deadlocks once the loop's buffers are all full or all empty
+    Cycle(Vec<Entity>),
+}
+
+impl std::fmt::Display for GraphIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphIssue::UnsuppliedInput { machine, item_type, via: None } => write!(f, "{machine} takes {item_type:?} but nothing supplies it"),
+            GraphIssue::UnsuppliedInput { machine, item_type, via: Some(via) } => write!(f, "{machine} takes {item_type:?} from {via}, which is starved"),
+            GraphIssue::UnconsumedOutput { machine, item_type, via: None } => write!(f, "{machine} makes {item_type:?} but nothing takes it"),
+            GraphIssue::UnconsumedOutput { machine, item_type, via: Some(via) } => write!(f, "{machine} makes {item_type:?} for {via}, which stalls"),
+            GraphIssue::Cycle(machines) => write!(f, "{} feed each other in a loop that can deadlock", names(machines)),
+        }
+    }
+}
+
+#[derive(Message, Clone, Debug)]
+/// Sent once when machines have been waiting on each other in a loop for DeadlockDetector::ticks
+pub struct Deadlocked {
+    pub tick: u64,
+    /// The loop, each machine waiting on the next for inputs or output space, and the last on the first
+    pub machines: Vec<Entity>,
+}
+
+impl std::fmt::Display for Deadlocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} are waiting on each other", names(&self.machines))
+    }
+}
+
+fn names(machines: &[Entity]) -> String {
+    machines.iter().map(Entity::to_string).collect::<Vec<String>>().join(", ")
+}
+
+#[derive(Resource, Clone, Debug)]
+/// Notices machines that have been waiting on each other for `ticks` ticks, see detect_deadlocks
+pub struct DeadlockDetector {
+    pub ticks: u64,
+    /// Tick each idle machine went idle on
+    idle_since: EntityHashMap<u64>,
+    /// Idle machines by the tick they will have been idle long enough on, soonest first
+    due: BinaryHeap<Reverse<(u64, Entity)>>,
+    /// Machines in `due`, which holds each at most once however often it goes idle again
+    queued: EntityHashSet,
+    /// Machines in deadlocks already reported, until they get going again
+    reported: EntityHashSet,
+}
+
+impl Default for DeadlockDetector {
+    fn default() -> Self {
+        Self { ticks: DEADLOCK_TICKS, idle_since: default(), due: default(), queued: default(), reported: default() }
+    }
+}
+
+#[derive(SystemParam)]
+/// The coupled machines as a graph, for finding what keeps parts of the factory from running
+pub struct FactoryGraph<'w, 's> {
+    couplings: CouplingGraph<'w, 's>,
+    machine_query: Query<'w, 's, (Entity, &'static Recipe, Option<&'static InputBank>, Option<&'static OutputBank>)>,
+    connector_query: Query<'w, 's, (&'static BufferType, Has<InputPort>, Has<OutputPort>)>,
+    buffer_query: Query<'w, 's, (&'static BufferSlot, &'static ItemBuffer)>,
+}
+
+impl FactoryGraph<'_, '_> {
+    /// Everything wrong with the way the factory is coupled, going by the recipes alone.
+    /// Items the player puts in by hand from the Inventory aren't counted on
+    pub fn issues(&self) -> Vec<GraphIssue> {
+        let mut issues = Vec::new();
+
+        // Starved machines spread downstream from the ones with an input nothing is coupled to
+        let mut starved = EntityHashSet::default();
+        let mut queue = VecDeque::new();
+        for (machine, _, inputs, _) in &self.machine_query {
+            for (BufferType(item_type), ..) in self.connector_query.iter_many(inputs.map(InputBank::get).map(Vec::as_slice).unwrap_or_default()).filter(|(_, coupled, _)| !coupled) {
+                issues.push(GraphIssue::UnsuppliedInput { machine, item_type: *item_type, via: None });
+                if starved.insert(machine) { queue.push_back(machine); }
+            }
+        }
+        while let Some(machine) = queue.pop_front() {
+            for coupling in self.couplings.downstream(machine) {
+                let Ok((BufferType(item_type), ..)) = self.connector_query.get(coupling.input) else { continue };
+                issues.push(GraphIssue::UnsuppliedInput { machine: coupling.dest, item_type: *item_type, via: Some(machine) });
+                if starved.insert(coupling.dest) { queue.push_back(coupling.dest); }
+            }
+        }
+
+        // Stalled machines spread upstream from the ones with an output nothing is coupled to
+        let mut stalled = EntityHashSet::default();
+        for (machine, _, _, outputs) in &self.machine_query {
+            for (BufferType(item_type), ..) in self.connector_query.iter_many(outputs.map(OutputBank::get).map(Vec::as_slice).unwrap_or_default()).filter(|(_, _, coupled)| !coupled) {
+                issues.push(GraphIssue::UnconsumedOutput { machine, item_type: *item_type, via: None });
+                if stalled.insert(machine) { queue.push_back(machine); }
+            }
+        }
+        while let Some(machine) = queue.pop_front() {
+            for coupling in self.couplings.upstream(machine) {
+                let Ok((BufferType(item_type), ..)) = self.connector_query.get(coupling.output) else { continue };
+                issues.push(GraphIssue::UnconsumedOutput { machine: coupling.src, item_type: *item_type, via: Some(machine) });
+                if stalled.insert(coupling.src) { queue.push_back(coupling.src); }
+            }
+        }
+
+        let machines = self.machine_query.iter().map(|(machine, ..)| machine);
+        issues.extend(cycles(machines, |machine| self.couplings.downstream(machine).into_iter().map(|coupling| coupling.dest).collect()).into_iter().map(GraphIssue::Cycle));
+        issues
+    }
+
+    /// Machines `machine` needs to hear from before it can craft again: the ones coupled to its inputs without enough
+    /// for a craft, and the ones coupled to its outputs without room for one
+    pub fn waits_on(&self, machine: Entity) -> Vec<Entity> {
+        let Ok((_, recipe, ..)) = self.machine_query.get(machine) else { return Vec::new() };
+        let lacks = |connector: Entity, stacks: &[Option<ItemStack>; 4], space: bool| self.buffer_query.get(connector).is_ok_and(|(BufferSlot(slot), buffer)| {
+            stacks.iter().flatten().nth(*slot).is_some_and(|stack| if space { buffer.remaining() } else { buffer.current } < stack.amount)
+        });
+
+        self.couplings.upstream(machine).into_iter().filter(|coupling| lacks(coupling.input, &recipe.inputs, false)).map(|coupling| coupling.src)
+            .chain(self.couplings.downstream(machine).into_iter().filter(|coupling| lacks(coupling.output, &recipe.outputs, true)).map(|coupling| coupling.dest))
+            .collect()
+    }
+}
+
+/// Groups of machines that can all reach each other following `edges`, searching from `roots`, in the order they're
+/// reached. Only groups that loop are kept, so more than one machine or a machine with an edge to itself
+fn cycles(roots: impl IntoIterator<Item = Entity>, edges: impl Fn(Entity) -> Vec<Entity>) -> Vec<Vec<Entity>> {
+    // Tarjan's, with an explicit path instead of recursion so long production lines don't overflow the stack
+    let mut order: EntityHashMap<(usize, usize)> = EntityHashMap::default();
+    let mut stack = Vec::new();
+    let mut on_stack = EntityHashSet::default();
+    let mut looped = EntityHashSet::default();
+    let mut cycles = Vec::new();
+
+    for root in roots {
+        if order.contains_key(&root) { continue; }
+        let index = order.len();
+        order.insert(root, (index, index));
+        stack.push(root);
+        on_stack.insert(root);
+        let mut path = vec![(root, edges(root))];
+
+        while let Some((machine, pending)) = path.last_mut() {
+            let machine = *machine;
+            if let Some(next) = pending.pop() {
+                if next == machine { looped.insert(machine); }
+                match order.get(&next) {
+                    None => {
+                        let index = order.len();
+                        order.insert(next, (index, index));
+                        stack.push(next);
+                        on_stack.insert(next);
+                        path.push((next, edges(next)));
+                    },
+                    Some(&(index, _)) if on_stack.contains(&next) => {
+                        let low = &mut order.get_mut(&machine).expect("Machines on the path are ordered").1;
+                        *low = (*low).min(index);
+                    },
+                    _ => {},
+                }
+                continue;
+            }
+
+            path.pop();
+            let (index, low) = order[&machine];
+            if let Some((parent, _)) = path.last() {
+                let parent_low = &mut order.get_mut(parent).expect("Machines on the path are ordered").1;
+                *parent_low = (*parent_low).min(low);
+            }
+            if low != index { continue; }
+
+            let mut group = Vec::new();
+            while let Some(member) = stack.pop() {
+                on_stack.remove(&member);
+                group.push(member);
+                if member == machine { break; }
+            }
+            if group.len() > 1 || looped.contains(&machine) {
+                group.reverse();
+                cycles.push(group);
+            }
+        }
+    }
+    cycles
+}
+
+/// Logs everything FactoryGraph::issues finds
+pub fn validate_factory(graph: FactoryGraph) {
+    let issues = graph.issues();
+    if issues.is_empty() {
+        info!("Every machine is supplied and drained, with no loops");
+    }
+    for issue in issues {
+        warn!("{issue}");
+    }
+}
+
+/// Keeps track of how long machines have been stalled. Once some have been for DeadlockDetector::ticks, looks for loops of
+/// them each waiting on the next for inputs or output space, which nothing will ever get going again
+pub fn detect_deadlocks(tick: Res<SimTick>, mut detector: ResMut<DeadlockDetector>, status_query: Query<(Entity, &MachineStatus), Changed<MachineStatus>>, mut removed: RemovedComponents<MachineStatus>, graph: FactoryGraph, mut deadlocked: MessageWriter<Deadlocked>) {
+    let detector = &mut *detector;
+    for (machine, status) in &status_query {
+        if status.is_stalled() && !detector.idle_since.contains_key(&machine) {
+            detector.idle_since.insert(machine, tick.0);
+            if detector.queued.insert(machine) {
+                detector.due.push(Reverse((tick.0 + detector.ticks, machine)));
+            }
+        } else if !status.is_stalled() {
+            detector.idle_since.remove(&machine);
+            detector.reported.remove(&machine);
+        }
+    }
+    for machine in removed.read() {
+        detector.idle_since.remove(&machine);
+        detector.reported.remove(&machine);
+    }
+
+    let mut newly_stuck = Vec::new();
+    while let Some(&Reverse((due, machine))) = detector.due.peek() && due <= tick.0 {
+        detector.due.pop();
+        detector.queued.remove(&machine);
+        let Some(&since) = detector.idle_since.get(&machine) else { continue };
+        // Machines that got going and went idle again since, or `ticks` was raised since they were queued
+        if tick.0 - since < detector.ticks {
+            detector.due.push(Reverse((since + detector.ticks, machine)));
+            detector.queued.insert(machine);
+            continue;
+        }
+        newly_stuck.push(machine);
+    }
+    if newly_stuck.is_empty() { return; }
+
+    let stuck = |machine: &Entity| detector.idle_since.get(machine).is_some_and(|since| tick.0 - since >= detector.ticks);
+    let loops = cycles(newly_stuck, |machine| graph.waits_on(machine).into_iter().filter(|waiting_on| stuck(waiting_on)).collect());
+    for machines in loops {
+        if machines.iter().all(|machine| detector.reported.contains(machine)) { continue; }
+        detector.reported.extend(machines.iter().copied());
+        deadlocked.write(Deadlocked { tick: tick.0, machines });
+    }
+}
+
+pub fn announce_deadlocks(mut deadlocked: MessageReader<Deadlocked>) {
+    for deadlock in deadlocked.read() {
+        warn!("Deadlock on tick {}: {deadlock}", deadlock.tick);
+    }
+}
@@ -0,0 +1,73 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use bevy::{ecs::{entity::hash_set::EntityHashSet, lifecycle::HookContext, world::DeferredWorld}, prelude::*};
+
+use crate::pipeline::machine::MachineStatus;
+
+#[derive(Resource, Clone, Debug, Default)]
+/// Which machines need looking at this tick. Machines sleep until their craft finishes, items arrive or leave,
+/// or they get changed, so idle and working machines cost nothing per tick
+pub struct Wakeups {
+    /// Machines ready_craft checks for a new craft
+    awake: EntityHashSet,
+    /// Machines craft collects finished crafts from
+    finished: EntityHashSet,
+    /// Working machines by the tick their craft finishes on, soonest first
+    timers: BinaryHeap<Reverse<(u64, Entity)>>,
+}
+
+impl Wakeups {
+    pub fn wake(&mut self, machine: Entity) {
+        self.awake.insert(machine);
+    }
+
+    /// Wakes `machine` on `tick` to finish its craft
+    pub fn wake_at(&mut self, machine: Entity, tick: u64) {
+        self.timers.push(Reverse((tick, machine)));
+    }
+
+    /// Wakes `machine` for whatever its status needs next: finishing its craft when it's done, or looking for a new one
+    pub fn track(&mut self, machine: Entity, status: MachineStatus) {
+        match status {
+            MachineStatus::Working(working) => self.wake_at(machine, working.finishes_at),
+            MachineStatus::CraftsFinished(_) => { self.finished.insert(machine); },
+            _ => self.wake(machine),
+        }
+    }
+
+    pub fn is_awake(&self, machine: Entity) -> bool {
+        self.awake.contains(&machine)
+    }
+
+    /// Takes the machines to check for a new craft, they go back to sleep unless something wakes them again
+    pub fn take_awake(&mut self) -> EntityHashSet {
+        std::mem::take(&mut self.awake)
+    }
+
+    /// Next machine whose timer is up by `tick`, if any
+    pub fn next_due(&mut self, tick: u64) -> Option<Entity> {
+        let Reverse((at, _)) = self.timers.peek()?;
+        if *at > tick { return None; }
+        self.timers.pop().map(|Reverse((_, machine))| machine)
+    }
+
+    pub fn finish(&mut self, machine: Entity) {
+        self.finished.insert(machine);
+    }
+
+    /// Takes the machines with finished crafts to collect
+    pub fn take_finished(&mut self) -> EntityHashSet {
+        std::mem::take(&mut self.finished)
+    }
+}
+
+/// Component hook waking a machine when something about it changes, e.g. it's built, switched off or gets a new recipe
+pub fn wake_machine(mut world: DeferredWorld, context: HookContext) {
+    let status = world.get::<MachineStatus>(context.entity).copied();
+    let Some(mut wakeups) = world.get_resource_mut::<Wakeups>() else { return };
+
+    match status {
+        Some(status) => wakeups.track(context.entity, status),
+        None => wakeups.wake(context.entity),
+    }
+}
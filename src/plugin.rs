@@ -0,0 +1,122 @@
+use bevy::{ecs::{intern::Interned, schedule::ScheduleLabel}, input::common_conditions::input_just_pressed, prelude::*};
+
+use crate::{alerts::{handle_alert_buttons, raise_alerts, setup_alert_feed, update_alert_feed, AlertConfig, Notifications, StatusHistory}, build::{build_controls, draw_build_ghost, draw_drag_ghost, handle_palette_buttons, place_machine, setup_build_palette, update_palette, BuildRules, BuildTool, Dragging}, camera::{frame_all, pan_camera, setup_camera, update_label_detail, zoom_camera, FRAME_ALL_KEY}, conservation::{check_craft, check_deliver_to_sinks, check_deposit_from_storage, check_push_outputs, check_ready_craft, check_research_in_labs, start_ledger, Conservation}, edit::{undo_redo, EditHistory}, grid::{CouplingRules, Grid}, inspector::{handle_circuit_buttons, handle_inspector_buttons, setup_inspector, update_inspector, Inspected}, inventory::{deposit_from_storage, setup_inventory_panel, update_inventory_panel, Inventory}, links::{configure_link_gizmos, draw_couplings, LinkGizmos}, modding::{refresh_research, Registry}, objectives::{announce_objectives, check_objectives, deliver_to_sinks, setup_objectives_hud, update_objectives_hud, Deliveries, ObjectiveCompleted, ObjectiveFailed, Objectives}, pipeline::{advance_tick, circuit::{read_sensors, Signals}, machine::{craft, push_outputs, ready_craft, tick_crafts, upgrade_buffers}, recipe::Recipes, validation::{announce_deadlocks, detect_deadlocks, validate_factory, DeadlockDetector, Deadlocked, VALIDATE_KEY}, wake::Wakeups, SimTick}, research::{announce_research, handle_research_buttons, research_in_labs, setup_research_panel, update_research_panel, ResearchTree, TechnologyResearched}, save::{load_game, save_game, LOAD_KEY, SAVE_KEY}, sim::{apply_sim_speed, catch_up, setup_sim_hud, sim_controls, skip_ahead, step_simulation, update_sim_hud, CatchUp, SimSpeed, SKIP_KEY, STEP_KEY, TICK_SECONDS}, ui::highlight_buttons, update_labels};
+
+/// The factory simulation, with its resources and systems, and optionally the UI to play it with
+pub struct FactoryPlugin {
+    /// Simulated seconds per tick, used for the fixed timestep
+    pub tick_seconds: f64,
+    /// Schedule the simulation runs in, once per tick
+    pub schedule: Interned<dyn ScheduleLabel>,
+    /// Adds the camera, HUDs, panels, build tools and keyboard controls. Leave it out to run headless
+    pub ui: bool,
+    /// Checks every tick that no items are made or lost outside of recipes, see Conservation. Costs a copy of every buffer
+    /// after each system, so it's meant for tests and debugging
+    pub check_conservation: bool,
+}
+
+impl Default for FactoryPlugin {
+    fn default() -> Self {
+        Self { tick_seconds: TICK_SECONDS, schedule: FixedUpdate.intern(), ui: true, check_conservation: false }
+    }
+}
+
+impl FactoryPlugin {
+    /// Just the simulation, e.g. for running it in tests or on a server
+    pub fn headless() -> Self {
+        Self { ui: false, ..default() }
+    }
+}
+
+#[derive(SystemSet, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+/// Phases of a simulation tick, in the order they run
+pub enum FactorySet {
+    /// Advances SimTick
+    Tick,
+    /// Sensors read their machines into Signals
+    Sense,
+    /// Machines start, advance and finish crafts
+    Craft,
+    /// Output buffers push into the input buffers they're coupled to
+    Transfer,
+    /// Sinks, storages, labs and mod behaviours use up what arrived
+    Consume,
+    /// Objectives are checked against what was consumed, and stuck machines raise alerts
+    Progress,
+    /// Machine labels show the result, only with the UI
+    Display,
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+/// Schedule FactoryPlugin runs the simulation in, for adding systems next to it
+pub struct FactorySchedule(pub Interned<dyn ScheduleLabel>);
+
+impl Plugin for FactoryPlugin {
+    fn build(&self, app: &mut App) {
+        // Mods added before FactoryPlugin have already put their recipes and technologies in these
+        app.world_mut().get_resource_or_insert_with(Recipes::init);
+        app.world_mut().get_resource_or_insert_with(ResearchTree::init);
+        refresh_research(app.world_mut());
+
+        app.init_resource::<Registry>()
+            .init_resource::<Grid>()
+            .init_resource::<CouplingRules>()
+            .init_resource::<BuildRules>()
+            .init_resource::<Inventory>()
+            .init_resource::<EditHistory>()
+            .init_resource::<SimTick>()
+            .init_resource::<Wakeups>()
+            .init_resource::<Signals>()
+            .init_resource::<Deliveries>()
+            .init_resource::<Objectives>()
+            .init_resource::<DeadlockDetector>()
+            .init_resource::<AlertConfig>()
+            .init_resource::<StatusHistory>()
+            .init_resource::<Notifications>()
+            .init_resource::<CatchUp>()
+            .add_message::<Deadlocked>()
+            .add_message::<ObjectiveCompleted>()
+            .add_message::<ObjectiveFailed>()
+            .add_message::<TechnologyResearched>()
+            .insert_resource(FactorySchedule(self.schedule))
+            .insert_resource(Time::<Fixed>::from_seconds(self.tick_seconds))
+            .configure_sets(self.schedule, (FactorySet::Tick, FactorySet::Sense, FactorySet::Craft, FactorySet::Transfer, FactorySet::Consume, FactorySet::Progress, FactorySet::Display).chain())
+            .add_systems(self.schedule, (
+                advance_tick.in_set(FactorySet::Tick),
+                read_sensors.in_set(FactorySet::Sense),
+                (ready_craft, tick_crafts, craft).chain().in_set(FactorySet::Craft),
+                push_outputs.in_set(FactorySet::Transfer),
+                (deliver_to_sinks, deposit_from_storage, research_in_labs, upgrade_buffers).chain().in_set(FactorySet::Consume),
+                (check_objectives, (detect_deadlocks, raise_alerts).chain()).in_set(FactorySet::Progress),
+            ))
+            .add_systems(Update, catch_up);
+
+        if self.check_conservation {
+            app.init_resource::<Conservation>()
+                .add_systems(self.schedule, (
+                    (start_ledger.before(ready_craft), check_ready_craft.after(ready_craft).before(tick_crafts), check_craft.after(craft)).in_set(FactorySet::Craft),
+                    check_push_outputs.after(push_outputs).in_set(FactorySet::Transfer),
+                    (
+                        check_deliver_to_sinks.after(deliver_to_sinks).before(deposit_from_storage),
+                        check_deposit_from_storage.after(deposit_from_storage).before(research_in_labs),
+                        check_research_in_labs.after(research_in_labs),
+                    ).in_set(FactorySet::Consume),
+                ));
+        }
+
+        if !self.ui { return; }
+
+        app.init_gizmo_group::<LinkGizmos>()
+            .init_resource::<BuildTool>()
+            .init_resource::<Dragging>()
+            .init_resource::<Inspected>()
+            .init_resource::<SimSpeed>()
+            .add_systems(self.schedule, update_labels.in_set(FactorySet::Display))
+            .add_systems(Startup, (setup_camera, configure_link_gizmos, setup_inspector, setup_sim_hud, setup_objectives_hud, setup_inventory_panel, setup_research_panel, setup_build_palette, setup_alert_feed))
+            .add_systems(Update, (pan_camera, zoom_camera, frame_all.run_if(input_just_pressed(FRAME_ALL_KEY)), update_label_detail, draw_couplings, handle_inspector_buttons, handle_circuit_buttons, update_inspector))
+            .add_systems(Update, (highlight_buttons, update_palette, handle_palette_buttons, build_controls, place_machine, draw_build_ghost, draw_drag_ghost, undo_redo))
+            .add_systems(Update, ((sim_controls, apply_sim_speed).chain(), step_simulation.run_if(input_just_pressed(STEP_KEY)), skip_ahead.run_if(input_just_pressed(SKIP_KEY)), update_sim_hud, update_objectives_hud, announce_objectives, update_inventory_panel))
+            .add_systems(Update, (update_research_panel, handle_research_buttons, announce_research, save_game.run_if(input_just_pressed(SAVE_KEY)), load_game.run_if(input_just_pressed(LOAD_KEY))))
+            .add_systems(Update, (validate_factory.run_if(input_just_pressed(VALIDATE_KEY)), announce_deadlocks, update_alert_feed, handle_alert_buttons));
+    }
+}
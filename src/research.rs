@@ -0,0 +1,221 @@
+use bevy::{platform::collections::{HashMap, HashSet}, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{pipeline::{machine::{BufferType, InputBank, ItemBuffer, MachineKind, MachineStats}, recipe::{ItemStack, Recipe}}, ui::{spawn_button, PANEL_COLOR}, ItemType};
+
+pub const RESEARCH_KEY: KeyCode = KeyCode::KeyT;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Unlock {
+    /// A single recipe, by the name the build palette shows for it
+    Recipe(String),
+    /// Every recipe of a machine kind
+    Machine(MachineKind),
+    /// Every buffer holds at least this many items
+    BufferSize(u64),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Technology {
+    pub name: String,
+    /// Items that have to be delivered to labs to research it
+    pub cost: Vec<ItemStack>,
+    /// Technologies that have to be researched first
+    pub requires: Vec<String>,
+    pub unlocks: Vec<Unlock>,
+}
+
+#[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+/// Every technology, loaded from assets/research.ron
+pub struct ResearchTree {
+    /// Available without researching anything
+    pub starting: Vec<Unlock>,
+    pub technologies: Vec<Technology>,
+}
+
+impl ResearchTree {
+    pub fn init() -> Self {
+        ron::from_str(include_str!("../assets/research.ron")).expect("assets/research.ron should be a valid ResearchTree")
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Technology> {
+        self.technologies.iter().find(|tech| tech.name == name)
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+/// How far along the research tree the player is
+pub struct Research {
+    /// Technology labs are working on
+    pub current: Option<String>,
+    /// Items delivered towards the current technology
+    pub progress: HashMap<ItemType, u64>,
+    pub completed: HashSet<String>,
+    unlocked: HashSet<Unlock>,
+}
+
+impl Research {
+    pub fn new(tree: &ResearchTree) -> Self {
+        let mut research = Self::default();
+        research.refresh_unlocks(tree);
+        research
+    }
+
+    pub fn is_unlocked(&self, recipe: &Recipe) -> bool {
+        self.unlocked.contains(&Unlock::Machine(recipe.machine_kind)) || self.unlocked.contains(&Unlock::Recipe(recipe.name()))
+    }
+
+    /// Size the BufferSize upgrades researched so far raise every buffer to, 0 without any
+    pub fn buffer_size(&self) -> u64 {
+        self.unlocked.iter().filter_map(|unlock| match unlock {
+            Unlock::BufferSize(size) => Some(*size),
+            _ => None,
+        }).max().unwrap_or_default()
+    }
+
+    /// Technologies that can be researched now: not done yet, with everything they require done
+    pub fn available<'a>(&self, tree: &'a ResearchTree) -> Vec<&'a Technology> {
+        tree.technologies.iter()
+            .filter(|tech| !self.completed.contains(&tech.name) && tech.requires.iter().all(|required| self.completed.contains(required)))
+            .collect()
+    }
+
+    /// Switches labs over to `name`, dropping whatever was delivered towards the previous technology
+    pub fn start(&mut self, name: &str) {
+        if self.current.as_deref() != Some(name) {
+            self.current = Some(name.to_string());
+            self.progress.clear();
+        }
+    }
+
+    /// How many more of `item_type` the current technology needs
+    pub fn needed(&self, tree: &ResearchTree, item_type: ItemType) -> u64 {
+        let Some(tech) = self.current.as_deref().and_then(|name| tree.get(name)) else { return 0 };
+        let cost: u64 = tech.cost.iter().filter(|stack| stack.item_type == item_type).map(|stack| stack.amount).sum();
+        cost.saturating_sub(self.progress.get(&item_type).copied().unwrap_or_default())
+    }
+
+    pub fn complete(&mut self, tree: &ResearchTree, name: &str) {
+        self.completed.insert(name.to_string());
+        if self.current.as_deref() == Some(name) {
+            self.current = None;
+            self.progress.clear();
+        }
+        self.refresh_unlocks(tree);
+    }
+
+    /// Recomputes what's unlocked from the starting entries and the completed technologies
+    pub fn refresh_unlocks(&mut self, tree: &ResearchTree) {
+        self.unlocked = tree.starting.iter().cloned()
+            .chain(tree.technologies.iter().filter(|tech| self.completed.contains(&tech.name)).flat_map(|tech| tech.unlocks.iter().cloned()))
+            .collect();
+    }
+}
+
+#[derive(Message, Clone, Debug)]
+pub struct TechnologyResearched(pub String);
+
+#[derive(Component, Clone, Debug)]
+pub struct ResearchPanel;
+
+#[derive(Component, Clone, Debug)]
+pub struct ResearchText;
+
+#[derive(Component, Clone, Debug, Default)]
+/// Holds a button per technology that can be researched, along with the technologies they were built for
+pub struct ResearchOptions(Vec<String>);
+
+#[derive(Component, Clone, Debug)]
+pub struct ResearchButton(pub String);
+
+/// Labs take in what the current technology still needs and finish it once it's all there
+pub fn research_in_labs(tree: Res<ResearchTree>, mut research: ResMut<Research>, mut researched: MessageWriter<TechnologyResearched>, mut lab_query: Query<(&MachineKind, &InputBank, &mut MachineStats)>, mut buffer_query: Query<(&BufferType, &mut ItemBuffer)>) {
+    let Some(tech) = research.current.as_deref().and_then(|name| tree.get(name)) else { return };
+
+    for (_, inputs, mut stats) in lab_query.iter_mut().filter(|(kind, ..)| **kind == MachineKind::Lab) {
+        let mut buffers = buffer_query.iter_many_mut(inputs.iter());
+        while let Some((BufferType(item_type), mut buffer)) = buffers.fetch_next() {
+            let taken = buffer.current.min(research.needed(&tree, *item_type));
+            if taken == 0 { continue; }
+
+            buffer.current -= taken;
+            stats.items_consumed += taken;
+            *research.progress.entry(*item_type).or_default() += taken;
+        }
+    }
+
+    if tech.cost.iter().all(|stack| research.needed(&tree, stack.item_type) == 0) {
+        let name = tech.name.clone();
+        research.complete(&tree, &name);
+        researched.write(TechnologyResearched(name));
+    }
+}
+
+pub fn announce_research(mut researched: MessageReader<TechnologyResearched>) {
+    for TechnologyResearched(name) in researched.read() {
+        info!("Researched {name}");
+    }
+}
+
+pub fn setup_research_panel(mut commands: Commands) {
+    commands.spawn((
+        ResearchPanel,
+        Node {
+            display: Display::None,
+            position_type: PositionType::Absolute,
+            flex_direction: FlexDirection::Column,
+            left: px(10),
+            top: px(260),
+            padding: UiRect::all(px(6)),
+            row_gap: px(5),
+            ..default()
+        },
+        BackgroundColor(PANEL_COLOR),
+        Interaction::default(),
+    )).with_children(|builder| {
+        builder.spawn((ResearchText, Text::new(""), TextFont {
+            font_size: 12.0,
+            ..default()
+        }));
+        builder.spawn((ResearchOptions::default(), Node {
+            flex_direction: FlexDirection::Column,
+            row_gap: px(5),
+            ..default()
+        }));
+    });
+}
+
+pub fn update_research_panel(mut commands: Commands, keys: Res<ButtonInput<KeyCode>>, tree: Res<ResearchTree>, research: Res<Research>, mut panel_query: Query<&mut Node, With<ResearchPanel>>, mut text_query: Query<&mut Text, With<ResearchText>>, mut options_query: Query<(Entity, &mut ResearchOptions)>) {
+    let Ok(mut panel) = panel_query.single_mut() else { return };
+    if keys.just_pressed(RESEARCH_KEY) {
+        panel.display = if panel.display == Display::None { Display::Flex } else { Display::None };
+    }
+    if !research.is_changed() { return; }
+
+    if let Ok(mut text) = text_query.single_mut() {
+        text.0 = match research.current.as_deref().and_then(|name| tree.get(name)) {
+            Some(tech) => tech.cost.iter().fold(format!("Researching {}", tech.name), |text, stack| {
+                format!("{text}\n  {:?} {}/{}", stack.item_type, stack.amount - research.needed(&tree, stack.item_type), stack.amount)
+            }),
+            None => String::from("Nothing being researched"),
+        };
+    }
+
+    let Ok((list, mut options)) = options_query.single_mut() else { return };
+    let available: Vec<String> = research.available(&tree).iter().map(|tech| tech.name.clone()).collect();
+    if available == options.0 { return; }
+
+    commands.entity(list).despawn_related::<Children>().with_children(|builder| {
+        for tech in research.available(&tree) {
+            let cost: Vec<String> = tech.cost.iter().map(|stack| format!("{:?} x{}", stack.item_type, stack.amount)).collect();
+            spawn_button(builder, format!("{} ({})", tech.name, cost.join(", ")), ResearchButton(tech.name.clone()));
+        }
+    });
+    options.0 = available;
+}
+
+pub fn handle_research_buttons(mut research: ResMut<Research>, button_query: Query<(&Interaction, &ResearchButton), Changed<Interaction>>) {
+    for (_, ResearchButton(name)) in button_query.iter().filter(|(interaction, _)| **interaction == Interaction::Pressed) {
+        research.start(name);
+    }
+}
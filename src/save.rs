@@ -0,0 +1,165 @@
+use std::{fs, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{edit::{remove_machine, EditHistory, MachineSnapshot}, inventory::Inventory, modding::Registry, objectives::{Deliveries, ObjectiveState, Objectives}, pipeline::{machine::{MachineId, MachineKind}, recipe::ItemStack, SimTick}, research::{Research, ResearchTree}, sim::CatchUp};
+
+pub const SAVE_PATH: &str = "save.ron";
+pub const SAVE_KEY: KeyCode = KeyCode::F5;
+pub const LOAD_KEY: KeyCode = KeyCode::F9;
+/// Offline progress stops after this long, however long the game was closed for
+pub const MAX_OFFLINE: Duration = Duration::from_secs(8 * 60 * 60);
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+/// What gets written to SAVE_PATH
+pub struct SaveGame {
+    pub research: ResearchSave,
+    /// Seconds since the Unix epoch when the game was saved, for offline progress. Older saves don't have it
+    #[serde(default)]
+    pub saved_at: Option<u64>,
+    /// Older saves don't have it either, they load onto whatever factory is there and get no offline progress
+    #[serde(default)]
+    pub factory: Option<FactorySave>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ResearchSave {
+    pub completed: Vec<String>,
+    pub current: Option<String>,
+    pub progress: Vec<ItemStack>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+/// The machines, with their buffers, crafts and couplings, and everything else the simulation was at
+pub struct FactorySave {
+    pub tick: u64,
+    pub machines: Vec<MachineSnapshot>,
+    pub inventory: Vec<ItemStack>,
+    pub deliveries: Vec<ItemStack>,
+    /// How far along each objective is, in order
+    pub objectives: Vec<(u64, ObjectiveState)>,
+}
+
+impl FactorySave {
+    pub fn capture(world: &World) -> Self {
+        let mut machines: Vec<(MachineId, Entity)> = world.try_query_filtered::<(Entity, &MachineId), With<MachineKind>>()
+            .map(|mut query| query.iter(world).map(|(machine, id)| (*id, machine)).collect())
+            .unwrap_or_default();
+        machines.sort_by_key(|(MachineId(id), _)| *id);
+
+        Self {
+            tick: world.resource::<SimTick>().0,
+            machines: machines.into_iter().filter_map(|(_, machine)| MachineSnapshot::capture(world, machine)).collect(),
+            inventory: world.resource::<Inventory>().iter().map(|(item_type, amount)| ItemStack::new(item_type, amount)).collect(),
+            deliveries: world.resource::<Deliveries>().iter().map(|(item_type, amount)| ItemStack::new(item_type, amount)).collect(),
+            objectives: world.resource::<Objectives>().0.iter().map(|objective| (objective.delivered, objective.state)).collect(),
+        }
+    }
+
+    /// Replaces the factory in `world` with the saved one. Undo history and any catching up left refer to the factory
+    /// that was there, so they're dropped
+    pub fn apply(self, world: &mut World) {
+        let machines: Vec<Entity> = world.query_filtered::<Entity, With<MachineKind>>().iter(world).collect();
+        for machine in machines {
+            remove_machine(world, machine);
+        }
+        *world.resource_mut::<EditHistory>() = EditHistory::default();
+        world.resource_mut::<CatchUp>().0 = 0;
+        world.resource_mut::<SimTick>().0 = self.tick;
+
+        // Couplings to machines that aren't back yet get made when those are
+        for snapshot in &self.machines {
+            if let Err(err) = snapshot.restore(world) {
+                warn!("Can't restore machine {:?}: {err:?}", snapshot.id);
+            }
+        }
+        world.flush();
+
+        let mut inventory = Inventory::default();
+        inventory.add_stacks(self.inventory);
+        world.insert_resource(inventory);
+        let mut deliveries = Deliveries::default();
+        for stack in self.deliveries {
+            deliveries.add(stack.item_type, stack.amount);
+        }
+        world.insert_resource(deliveries);
+        for (objective, (delivered, state)) in world.resource_mut::<Objectives>().0.iter_mut().zip(self.objectives) {
+            objective.delivered = delivered;
+            objective.state = state;
+        }
+    }
+}
+
+impl SaveGame {
+    pub fn capture(world: &World) -> Self {
+        let research = world.resource::<Research>();
+        let mut completed: Vec<String> = research.completed.iter().cloned().collect();
+        completed.sort();
+
+        Self {
+            research: ResearchSave {
+                completed,
+                current: research.current.clone(),
+                progress: research.progress.iter().map(|(item_type, amount)| ItemStack::new(*item_type, *amount)).collect(),
+            },
+            saved_at: SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|since| since.as_secs()),
+            factory: Some(FactorySave::capture(world)),
+        }
+    }
+
+    /// Reads a save, looking up any custom item types and machine kinds in it in `registry`
+    pub fn parse(text: &str, registry: &Registry) -> ron::error::SpannedResult<Self> {
+        registry.loading(|| ron::from_str(text))
+    }
+
+    pub fn apply(self, world: &mut World) {
+        world.resource_scope(|world, tree: Mut<ResearchTree>| {
+            let mut research = world.resource_mut::<Research>();
+            research.completed = self.research.completed.into_iter().collect();
+            research.current = self.research.current;
+            research.progress = self.research.progress.into_iter().map(|stack| (stack.item_type, stack.amount)).collect();
+            research.refresh_unlocks(&tree);
+        });
+        if let Some(factory) = self.factory {
+            factory.apply(world);
+        }
+    }
+}
+
+pub fn save_game(world: &mut World) {
+    let save = SaveGame::capture(world);
+    let result = ron::ser::to_string_pretty(&save, ron::ser::PrettyConfig::default()).map_err(|err| err.to_string())
+        .and_then(|text| fs::write(SAVE_PATH, text).map_err(|err| err.to_string()));
+
+    match result {
+        Ok(()) => info!("Saved to {SAVE_PATH}"),
+        Err(err) => warn!("Can't save to {SAVE_PATH}: {err}"),
+    }
+}
+
+fn read_save(world: &World) -> Option<SaveGame> {
+    let text = fs::read_to_string(SAVE_PATH).ok()?;
+
+    SaveGame::parse(&text, world.resource::<Registry>()).inspect_err(|err| warn!("Can't load {SAVE_PATH}: {err}")).ok()
+}
+
+/// Loads SAVE_PATH if there is one
+pub fn load_game(world: &mut World) {
+    let Some(save) = read_save(world) else { return };
+
+    save.apply(world);
+    info!("Loaded {SAVE_PATH}");
+}
+
+/// Loads SAVE_PATH when the game starts, then has catch_up fast-forward the factory through the time it was closed for,
+/// up to MAX_OFFLINE. Only saves with the factory in them get offline progress, anything else would run the default layout
+pub fn resume_game(world: &mut World) {
+    let Some(save) = read_save(world) else { return };
+    let offline = save.saved_at.filter(|_| save.factory.is_some()).and_then(|saved_at| SystemTime::now().duration_since(UNIX_EPOCH + Duration::from_secs(saved_at)).ok()).unwrap_or_default().min(MAX_OFFLINE);
+
+    save.apply(world);
+    let ticks = offline.div_duration_f64(world.resource::<Time<Fixed>>().timestep()) as u64;
+    world.resource_mut::<CatchUp>().0 += ticks;
+    info!("Loaded {SAVE_PATH}, catching up on {} s of offline progress", offline.as_secs());
+}
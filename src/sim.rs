@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use bevy::{app::FixedMain, prelude::*};
+
+use crate::pipeline::{fast_forward::fast_forward_within, SimTick};
+
+/// Default length of a simulation tick, see FactoryPlugin::tick_seconds
+pub const TICK_SECONDS: f64 = 0.1;
+pub const SPEEDS: [f32; 4] = [0.25, 1.0, 4.0, 16.0];
+/// At most this many ticks get simulated per frame, whatever the speed.
+/// When frames take too long to keep up, the simulation slows down instead of piling up ticks to catch up on
+pub const MAX_TICKS_PER_FRAME: u32 = 32;
+/// At most this many ticks get stepped through one at a time per frame while catching up, see catch_up
+pub const MAX_STEPPED_TICKS_PER_FRAME: u64 = 256;
+
+pub const PAUSE_KEY: KeyCode = KeyCode::Space;
+pub const STEP_KEY: KeyCode = KeyCode::Period;
+pub const SPEED_KEYS: [KeyCode; 4] = [KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4];
+pub const SKIP_KEY: KeyCode = KeyCode::End;
+/// Simulated time SKIP_KEY skips ahead by
+pub const SKIP_DURATION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+/// Simulated seconds per real second
+pub struct SimSpeed(pub f32);
+
+impl Default for SimSpeed {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Ticks a skip or offline progress still has to fast-forward through
+pub struct CatchUp(pub u64);
+
+#[derive(Component, Clone, Debug)]
+pub struct SimHud;
+
+pub fn setup_sim_hud(mut commands: Commands) {
+    commands.spawn((
+        SimHud,
+        Text::new(""),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            left: px(10),
+            top: px(10),
+            ..default()
+        },
+    ));
+}
+
+pub fn sim_controls(keys: Res<ButtonInput<KeyCode>>, mut speed: ResMut<SimSpeed>, mut time: ResMut<Time<Virtual>>) {
+    if keys.just_pressed(PAUSE_KEY) {
+        if time.is_paused() { time.unpause() } else { time.pause() }
+    }
+
+    for (key, multiplier) in SPEED_KEYS.iter().zip(SPEEDS) {
+        if keys.just_pressed(*key) {
+            speed.0 = multiplier;
+        }
+    }
+}
+
+pub fn apply_sim_speed(speed: Res<SimSpeed>, fixed: Res<Time<Fixed>>, mut time: ResMut<Time<Virtual>>) {
+    if !speed.is_changed() { return; }
+
+    time.set_relative_speed(speed.0);
+    // Virtual time clamps real time before scaling it, so this caps the simulated time per frame
+    time.set_max_delta(fixed.timestep().mul_f64(MAX_TICKS_PER_FRAME as f64 / speed.0 as f64));
+}
+
+/// Runs a single fixed tick, for stepping through the simulation while it's paused
+pub fn step_simulation(world: &mut World) {
+    if !world.resource::<Time<Virtual>>().is_paused() { return; }
+
+    *world.resource_mut::<Time>() = world.resource::<Time<Fixed>>().as_generic();
+    world.run_schedule(FixedMain);
+    *world.resource_mut::<Time>() = world.resource::<Time<Virtual>>().as_generic();
+}
+
+/// Fast-forwards the simulation by SKIP_DURATION, see catch_up
+pub fn skip_ahead(world: &mut World) {
+    let ticks = SKIP_DURATION.div_duration_f64(world.resource::<Time<Fixed>>().timestep()) as u64;
+    world.resource_mut::<CatchUp>().0 += ticks;
+    info!("Skipping ahead {} ticks", ticks);
+}
+
+/// Fast-forwards through what's left in CatchUp. What the flow model can't work out gets stepped through at
+/// MAX_STEPPED_TICKS_PER_FRAME a frame, so a long skip is spread over frames instead of freezing the game
+pub fn catch_up(world: &mut World) {
+    let CatchUp(ticks) = *world.resource::<CatchUp>();
+    if ticks == 0 { return; }
+
+    let remaining = fast_forward_within(world, ticks, MAX_STEPPED_TICKS_PER_FRAME);
+    world.resource_mut::<CatchUp>().0 = remaining;
+    if remaining == 0 {
+        info!("Caught up");
+    }
+}
+
+pub fn update_sim_hud(tick: Res<SimTick>, speed: Res<SimSpeed>, catch_up: Res<CatchUp>, time: Res<Time<Virtual>>, real_time: Res<Time<Real>>, mut hud_query: Query<&mut Text, With<SimHud>>) {
+    let Ok(mut hud) = hud_query.single_mut() else { return };
+
+    hud.0 = if catch_up.0 > 0 {
+        format!("Tick {} - Catching up, {} ticks to go", tick.0, catch_up.0)
+    } else if time.is_paused() {
+        format!("Tick {} - Paused", tick.0)
+    } else if real_time.delta() > time.max_delta() {
+        format!("Tick {} - {}x (lagging)", tick.0, speed.0)
+    } else {
+        format!("Tick {} - {}x", tick.0, speed.0)
+    };
+}
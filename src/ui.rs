@@ -0,0 +1,38 @@
+use bevy::prelude::*;
+
+pub const BUTTON_COLOR: Color = Color::linear_rgb(0.15, 0.15, 0.15);
+pub const BUTTON_HOVERED_COLOR: Color = Color::linear_rgb(0.25, 0.25, 0.25);
+pub const PANEL_COLOR: Color = Color::linear_rgba(0.0, 0.0, 0.0, 0.9);
+
+/// Spawns a small text button, `action` tells the panel's handler what pressing it does
+pub fn spawn_button(builder: &mut ChildSpawnerCommands, label: impl Into<String>, action: impl Bundle) {
+    builder.spawn((
+        Button,
+        action,
+        Node {
+            padding: UiRect::axes(px(6), px(3)),
+            ..default()
+        },
+        BackgroundColor(BUTTON_COLOR),
+        BorderRadius::all(px(3)),
+    )).with_child((Text::new(label), TextFont {
+        font_size: 12.0,
+        ..default()
+    }));
+}
+
+type ChangedButton = (Changed<Interaction>, With<Button>);
+
+pub fn highlight_buttons(mut button_query: Query<(&Interaction, &mut BackgroundColor), ChangedButton>) {
+    for (interaction, mut color) in &mut button_query {
+        color.0 = match interaction {
+            Interaction::None => BUTTON_COLOR,
+            Interaction::Hovered | Interaction::Pressed => BUTTON_HOVERED_COLOR,
+        };
+    }
+}
+
+/// The pointer is over a panel or button, so clicks shouldn't reach the world behind it
+pub fn pointer_over_ui(interaction_query: &Query<&Interaction>) -> bool {
+    interaction_query.iter().any(|interaction| *interaction != Interaction::None)
+}
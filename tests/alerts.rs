@@ -0,0 +1,147 @@
+mod common;
+
+use std::time::Duration;
+
+use common::TestFactory;
+use factory::{alerts::{AlertConfig, AlertKind, Thresholds}, pipeline::{machine::MachineKind, recipe::{ItemStack, Recipe}, validation::DEADLOCK_TICKS}, ItemType};
+
+/// 60 s at the default tick rate, how long a machine can be starved before it raises an alert
+const STARVED_TICKS: u64 = 600;
+/// 5 min, the same for full outputs
+const FULL_TICKS: u64 = 3000;
+
+#[test]
+fn starved_machine_raises_an_alert() {
+    let mut factory = TestFactory::new();
+    let transformer = factory.transformer(ItemType::Storage);
+
+    // Idle from the first tick on
+    factory.tick(STARVED_TICKS);
+    assert!(factory.notifications().is_empty());
+    factory.tick(1);
+
+    let notifications = factory.notifications();
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].kind, AlertKind::Starved);
+    assert_eq!(notifications[0].machines, [transformer]);
+    assert_eq!(notifications[0].to_string(), "Transformer waiting for input for 60 s");
+}
+
+#[test]
+fn full_output_waits_for_its_own_threshold() {
+    let mut factory = TestFactory::new();
+    let producer = factory.producer(ItemType::Input);
+
+    // 50 crafts fill the output by tick 500, the producer is Full from the next tick on
+    factory.tick(501 + FULL_TICKS - 1);
+    assert!(factory.notifications().is_empty());
+    factory.tick(1);
+
+    let notifications = factory.notifications();
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].machines, [producer]);
+    assert_eq!(notifications[0].to_string(), "Producer output full for 5 min");
+}
+
+#[test]
+fn thresholds_are_per_machine_kind() {
+    let mut factory = TestFactory::new();
+    factory.world_mut().resource_mut::<AlertConfig>().set(MachineKind::Transformer, Thresholds { starved: None, full: None });
+    factory.transformer(ItemType::Storage);
+    factory.sink(ItemType::Storage);
+    let combinator = factory.combinator(ItemType::Transformer);
+
+    factory.tick(STARVED_TICKS * 2);
+    let notifications = factory.notifications();
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].machines, [combinator]);
+}
+
+#[test]
+fn alerts_at_the_same_time_are_grouped() {
+    let mut factory = TestFactory::new();
+    let machines: Vec<_> = (0..3).map(|_| factory.combinator(ItemType::Transformer)).collect();
+
+    factory.tick(STARVED_TICKS + 1);
+    let notifications = factory.notifications();
+    assert_eq!(notifications.len(), 1);
+    assert!(machines.iter().all(|machine| notifications[0].machines.contains(machine)));
+    assert_eq!(notifications[0].to_string(), "3 Combinators waiting for input for 60 s");
+}
+
+#[test]
+fn grouping_looks_past_other_notifications() {
+    let mut factory = TestFactory::new();
+    let first = factory.combinator(ItemType::Transformer);
+    factory.tick(3);
+    factory.transformer(ItemType::Storage);
+    factory.tick(3);
+    let second = factory.combinator(ItemType::Transformer);
+
+    factory.tick(STARVED_TICKS + 1);
+    let notifications = factory.notifications();
+    assert_eq!(notifications.len(), 2, "The transformer's alert came in between, but the combinators' are still grouped");
+    assert_eq!(notifications[0].machines, [first, second]);
+    assert_eq!(notifications[1].machine_kind, Some(MachineKind::Transformer));
+}
+
+#[test]
+fn notifications_say_how_long_machines_were_stuck() {
+    let mut factory = TestFactory::new();
+    factory.transformer(ItemType::Storage);
+    factory.tick(100);
+
+    // Lowered after the alert was already due at 60 s, so that's when it's raised
+    factory.world_mut().resource_mut::<AlertConfig>().default.starved = Some(Duration::from_secs(30));
+    factory.tick(STARVED_TICKS - 99);
+    let notifications = factory.notifications();
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].to_string(), "Transformer waiting for input for 60 s");
+}
+
+#[test]
+fn same_alert_is_rate_limited() {
+    let mut factory = TestFactory::new();
+    let transformer = factory.transformer(ItemType::Storage);
+    factory.tick(STARVED_TICKS + 1);
+    assert_eq!(factory.notifications().len(), 1);
+
+    // Crafting and running dry again within the cooldown doesn't raise it again
+    factory.fill(transformer, ItemType::Input, 5);
+    factory.tick(STARVED_TICKS + 50);
+    assert_eq!(factory.notifications().len(), 1);
+
+    // Past the cooldown it does
+    factory.tick(FULL_TICKS);
+    factory.fill(transformer, ItemType::Input, 5);
+    factory.tick(STARVED_TICKS + 50);
+    assert_eq!(factory.notifications().len(), 2);
+}
+
+#[test]
+fn cooldown_is_configurable() {
+    let mut factory = TestFactory::new();
+    factory.world_mut().resource_mut::<AlertConfig>().cooldown = Duration::ZERO;
+    let transformer = factory.transformer(ItemType::Storage);
+    factory.tick(STARVED_TICKS + 1);
+
+    factory.fill(transformer, ItemType::Input, 5);
+    factory.tick(STARVED_TICKS + 50);
+    assert_eq!(factory.notifications().len(), 2);
+}
+
+#[test]
+fn deadlocks_go_in_the_feed() {
+    let mut factory = TestFactory::new();
+    factory.world_mut().resource_mut::<AlertConfig>().default = Thresholds { starved: None, full: None };
+    let first = factory.machine(Recipe::new(MachineKind::Transformer, &[ItemStack::new(ItemType::Input, 1)], &[ItemStack::new(ItemType::Output, 1)], 10));
+    let second = factory.machine(Recipe::new(MachineKind::Transformer, &[ItemStack::new(ItemType::Output, 1)], &[ItemStack::new(ItemType::Input, 1)], 10));
+    factory.couple(first, second, ItemType::Output);
+    factory.couple(second, first, ItemType::Input);
+
+    factory.tick(DEADLOCK_TICKS + 1);
+    let notifications = factory.notifications();
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].kind, AlertKind::Deadlocked);
+    assert_eq!(notifications[0].to_string(), "2 machines deadlocked on each other for 30 s");
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use bevy::prelude::*;
+use common::TestFactory;
+use factory::{inventory::Inventory, pipeline::{machine::{input_buffers, output_buffers, Capacity, MachineKind, OutputBank}, recipe::{ItemStack, Recipe}}, research::{Research, ResearchTree}, ItemType};
+
+fn input_capacity(factory: &TestFactory, machine: Entity) -> u64 {
+    input_buffers(factory.world(), machine)[0].buffer.max
+}
+
+fn output_capacity(factory: &TestFactory, machine: Entity) -> u64 {
+    output_buffers(factory.world(), machine)[0].buffer.max
+}
+
+#[test]
+fn batch_bigger_than_the_default_buffer_still_crafts() {
+    let mut factory = TestFactory::new();
+    let producer = factory.producer(ItemType::Input);
+    let transformer = factory.machine(Recipe::new(MachineKind::Transformer, &[ItemStack::new(ItemType::Input, 80)], &[ItemStack::new(ItemType::Storage, 1)], 20));
+    factory.couple(producer, transformer, ItemType::Input);
+    assert_eq!(input_capacity(&factory, transformer), 80);
+
+    // The 80th Input arrives on tick 800, the craft starts on tick 801
+    factory.tick(820);
+    assert_eq!(factory.outputs(transformer), [1]);
+}
+
+#[test]
+fn recipe_sets_buffer_size() {
+    let mut factory = TestFactory::new();
+    let recipe = factory.recipes().get_producer(ItemType::Input).expect("There's a producer recipe for it").with_buffer_size(20);
+    let producer = factory.machine(recipe);
+    assert_eq!(output_capacity(&factory, producer), 20);
+
+    factory.tick(300);
+    assert_eq!(factory.outputs(producer), [20]);
+}
+
+#[test]
+fn buffers_grow_to_fit_a_batch_times_mult() {
+    let mut factory = TestFactory::new();
+    let transformer = factory.transformer(ItemType::Storage);
+    factory.set_mult(transformer, 20);
+    factory.world_mut().flush();
+    assert_eq!(input_capacity(&factory, transformer), 100);
+    assert_eq!(output_capacity(&factory, transformer), 50);
+
+    factory.set_mult(transformer, 2);
+    factory.world_mut().flush();
+    assert_eq!(input_capacity(&factory, transformer), 50);
+}
+
+#[test]
+fn port_capacity_overrides_recipe_and_spills_into_inventory() {
+    let mut factory = TestFactory::new();
+    let producer = factory.producer(ItemType::Input);
+    factory.tick(100);
+    assert_eq!(factory.outputs(producer), [10]);
+
+    let connector = factory.world().get::<OutputBank>(producer).expect("Producer has outputs").get()[0];
+    factory.world_mut().entity_mut(connector).insert(Capacity(4));
+    factory.world_mut().flush();
+    assert_eq!(factory.outputs(producer), [4]);
+    assert_eq!(output_capacity(&factory, producer), 4);
+    assert_eq!(factory.world().resource::<Inventory>().count(ItemType::Input), 6);
+
+    factory.world_mut().entity_mut(connector).remove::<Capacity>();
+    factory.world_mut().flush();
+    assert_eq!(output_capacity(&factory, producer), 50);
+}
+
+#[test]
+fn research_upgrades_every_buffer() {
+    let mut factory = TestFactory::new();
+    let producer = factory.producer(ItemType::Input);
+    let tree = factory.world().resource::<ResearchTree>().clone();
+    for name in ["Storage", "Bigger buffers"] {
+        factory.world_mut().resource_mut::<Research>().complete(&tree, name);
+    }
+
+    factory.tick(1);
+    assert_eq!(output_capacity(&factory, producer), 100);
+    // Built after the upgrade
+    let transformer = factory.transformer(ItemType::Storage);
+    assert_eq!(input_capacity(&factory, transformer), 100);
+}
@@ -0,0 +1,156 @@
+//! Headless factory for integration tests: declare machines and couplings, run exact numbers of ticks, look at the buffers
+
+#![allow(dead_code)]
+
+use bevy::{ecs::system::RunSystemOnce, prelude::*};
+use factory::{alerts::{Notification, Notifications}, bind_output, conservation::{Conservation, Violation}, grid::{Grid, Rotation}, pipeline::{machine::{input_buffers, output_buffers, BufferType, InputBank, ItemBuffer, MachineInput, MachineStats, MachineStatus, Mult, OutputBank, OutputPort}, recipe::{Recipe, Recipes}, validation::{Deadlocked, FactoryGraph, GraphIssue}, wake::Wakeups, SimTick}, plugin::FactoryPlugin, spawn_machine, ItemType};
+
+/// Cells between machines, enough for the widest footprint
+const SPACING: i32 = 10;
+
+pub struct TestFactory {
+    pub app: App,
+    machines: i32,
+}
+
+impl TestFactory {
+    /// An empty factory running FactoryPlugin without the UI, checking items are conserved
+    pub fn new() -> Self {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, FactoryPlugin { check_conservation: true, ..FactoryPlugin::headless() }));
+        app.finish();
+        app.cleanup();
+
+        Self { app, machines: 0 }
+    }
+
+    pub fn world(&self) -> &World {
+        self.app.world()
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        self.app.world_mut()
+    }
+
+    pub fn recipes(&self) -> &Recipes {
+        self.world().resource::<Recipes>()
+    }
+
+    pub fn producer(&mut self, output: ItemType) -> Entity {
+        let recipe = self.recipes().get_producer(output).expect("There's a producer recipe for it");
+        self.machine(recipe)
+    }
+
+    pub fn transformer(&mut self, output: ItemType) -> Entity {
+        let recipe = self.recipes().get_transformer(output).expect("There's a transformer recipe for it");
+        self.machine(recipe)
+    }
+
+    pub fn combinator(&mut self, output: ItemType) -> Entity {
+        let recipe = self.recipes().get_combinator(output).expect("There's a combinator recipe for it");
+        self.machine(recipe)
+    }
+
+    pub fn sink(&mut self, input: ItemType) -> Entity {
+        let recipe = self.recipes().get_sink(input).expect("There's a sink recipe for it");
+        self.machine(recipe)
+    }
+
+    /// Builds a machine crafting `recipe`, in a row to the right of the previous one
+    pub fn machine(&mut self, recipe: Recipe) -> Entity {
+        let cell = IVec2::new(self.machines * SPACING, 0);
+        self.machines += 1;
+
+        let world = self.app.world_mut();
+        let machine = world.resource_scope(|world, mut grid: Mut<Grid>| {
+            spawn_machine(&mut world.commands(), &mut grid, "Machine", recipe, cell, Rotation::East).expect("Machines are spaced out enough to fit")
+        });
+        world.flush();
+        machine
+    }
+
+    /// Couples `src`'s output of `item_type` to `dest`'s input, panicking if they can't be
+    pub fn couple(&mut self, src: Entity, dest: Entity, item_type: ItemType) {
+        assert!(self.try_couple(src, dest, item_type), "{src} and {dest} should be coupled for {item_type:?}");
+    }
+
+    /// Couples `src`'s output of `item_type` to `dest`'s input if bind_output allows it, returning whether they are
+    pub fn try_couple(&mut self, src: Entity, dest: Entity, item_type: ItemType) -> bool {
+        let world = self.app.world_mut();
+        bind_output(&mut world.commands(), src, dest, item_type);
+        world.flush();
+        world.get::<OutputBank>(src).is_some_and(|bank| bank.iter().any(|output| {
+            world.get::<BufferType>(output).is_some_and(|BufferType(output_type)| *output_type == item_type) && world.get::<OutputPort>(output).and_then(|OutputPort(input)| world.get::<MachineInput>(*input)).is_some_and(|MachineInput(machine)| *machine == dest)
+        }))
+    }
+
+    pub fn set_mult(&mut self, machine: Entity, mult: u64) {
+        self.world_mut().entity_mut(machine).insert(Mult(mult));
+    }
+
+    /// Puts `amount` items into `machine`'s input buffer for `item_type`, like fill_inputs would
+    pub fn fill(&mut self, machine: Entity, item_type: ItemType, amount: u64) {
+        let world = self.world_mut();
+        let inputs = world.get::<InputBank>(machine).expect("Machine has inputs");
+        let input = inputs.iter().find(|input| world.get::<BufferType>(*input).is_some_and(|BufferType(input_type)| *input_type == item_type)).expect("Machine takes the item type");
+        world.get_mut::<ItemBuffer>(input).expect("Connectors have a buffer").current += amount;
+        self.world_mut().resource_mut::<Wakeups>().wake(machine);
+    }
+
+    /// Runs exactly `ticks` fixed ticks, panicking on the first tick items aren't conserved
+    pub fn tick(&mut self, ticks: u64) {
+        let start = self.tick_count();
+        for _ in 0..ticks {
+            self.app.world_mut().run_schedule(FixedUpdate);
+            let violations = self.violations();
+            assert!(violations.is_empty(), "Items not conserved:\n{}", violations.iter().map(|v| v.to_string()).collect::<Vec<String>>().join("\n"));
+        }
+        assert_eq!(self.tick_count(), start + ticks);
+    }
+
+    pub fn violations(&self) -> &[Violation] {
+        &self.world().resource::<Conservation>().violations
+    }
+
+    /// Everything FactoryGraph finds wrong with the couplings
+    pub fn issues(&mut self) -> Vec<GraphIssue> {
+        self.world_mut().run_system_once(|graph: FactoryGraph| graph.issues()).expect("FactoryGraph only reads")
+    }
+
+    /// The alert feed, oldest first
+    pub fn notifications(&self) -> Vec<Notification> {
+        self.world().resource::<Notifications>().feed.iter().cloned().collect()
+    }
+
+    /// Deadlocks detected so far
+    pub fn deadlocks(&self) -> Vec<Deadlocked> {
+        let messages = self.world().resource::<Messages<Deadlocked>>();
+        messages.get_cursor().read(messages).cloned().collect()
+    }
+
+    pub fn tick_count(&self) -> u64 {
+        self.world().resource::<SimTick>().0
+    }
+
+    /// Items in each of `machine`'s input buffers
+    pub fn inputs(&self, machine: Entity) -> Vec<u64> {
+        input_buffers(self.world(), machine).iter().map(|input| input.buffer.current).collect()
+    }
+
+    /// Items in each of `machine`'s output buffers
+    pub fn outputs(&self, machine: Entity) -> Vec<u64> {
+        output_buffers(self.world(), machine).iter().map(|output| output.buffer.current).collect()
+    }
+
+    pub fn status(&self, machine: Entity) -> MachineStatus {
+        *self.world().get::<MachineStatus>(machine).expect("Machine has a status")
+    }
+
+    pub fn stats(&self, machine: Entity) -> MachineStats {
+        *self.world().get::<MachineStats>(machine).expect("Machine has stats")
+    }
+
+    pub fn is_working(&self, machine: Entity) -> bool {
+        matches!(self.status(machine), MachineStatus::Working(_))
+    }
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use common::TestFactory;
+use bevy::prelude::*;
+use factory::{inventory::Inventory, pipeline::machine::{push_outputs, Capacity, ItemBuffer, MachineOutput}, plugin::FactorySet, ItemType};
+
+#[test]
+fn running_factory_conserves_items() {
+    let mut factory = TestFactory::new();
+    let inputs = factory.producer(ItemType::Input);
+    let outputs = factory.producer(ItemType::Output);
+    let combinator = factory.combinator(ItemType::Transformer);
+    let sink = factory.sink(ItemType::Transformer);
+    factory.couple(inputs, combinator, ItemType::Input);
+    factory.couple(outputs, combinator, ItemType::Output);
+    factory.couple(combinator, sink, ItemType::Transformer);
+
+    // Long enough for buffers to fill and back up behind the combinator
+    factory.tick(2000);
+    assert!(factory.stats(sink).items_consumed > 0);
+    assert!(factory.violations().is_empty());
+}
+
+#[test]
+fn overfilled_buffer_is_reported() {
+    let mut factory = TestFactory::new();
+    let transformer = factory.transformer(ItemType::Storage);
+    factory.fill(transformer, ItemType::Input, 60);
+
+    // Run the schedule directly, tick() would panic on the violation
+    factory.world_mut().run_schedule(FixedUpdate);
+    let violation = factory.violations().first().expect("The overfilled buffer is found");
+    assert_eq!(violation.machine, Some(transformer));
+    assert_eq!(violation.tick, 1);
+}
+
+#[test]
+fn items_made_outside_a_recipe_are_reported() {
+    let mut factory = TestFactory::new();
+    let producer = factory.producer(ItemType::Input);
+    factory.tick(5);
+
+    // A buggy system making an item in the middle of the tick
+    factory.app.add_systems(FixedUpdate, make_item.in_set(FactorySet::Transfer).before(push_outputs));
+    factory.world_mut().run_schedule(FixedUpdate);
+    assert!(factory.violations().iter().any(|violation| violation.machine == Some(producer) && violation.system == "push_outputs"));
+}
+
+fn make_item(mut outputs: Query<&mut ItemBuffer, With<MachineOutput>>) {
+    for mut output in &mut outputs {
+        output.current += 1;
+    }
+}
+
+#[test]
+fn shrinking_buffers_mid_tick_is_in_the_ledger() {
+    let mut factory = TestFactory::new();
+    let producer = factory.producer(ItemType::Input);
+    factory.tick(100);
+    assert_eq!(factory.outputs(producer), [10]);
+
+    // Something like a circuit or a mod cutting the buffer down while the tick runs, the overflow goes to the Inventory
+    factory.app.add_systems(FixedUpdate, shrink_outputs.in_set(FactorySet::Transfer).before(push_outputs));
+    factory.tick(1);
+    assert_eq!(factory.outputs(producer), [4]);
+    assert_eq!(factory.world().resource::<Inventory>().count(ItemType::Input), 6);
+}
+
+fn shrink_outputs(mut commands: Commands, outputs: Query<Entity, (With<MachineOutput>, Without<Capacity>)>) {
+    for output in &outputs {
+        commands.entity(output).insert(Capacity(4));
+    }
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use bevy::prelude::*;
+use common::TestFactory;
+use factory::{edit::{record_move, record_set_recipe, step_history}, grid::{GridPos, Rotation}, inventory::Inventory, pipeline::recipe::Recipe, ItemType};
+
+fn move_machine(factory: &mut TestFactory, machine: Entity, cell: IVec2, rotation: Rotation) {
+    let world = factory.world_mut();
+    record_move(&mut world.commands(), machine, cell, rotation);
+    world.flush();
+}
+
+fn set_recipe(factory: &mut TestFactory, machine: Entity, recipe: Recipe) {
+    let world = factory.world_mut();
+    record_set_recipe(&mut world.commands(), machine, recipe);
+    world.flush();
+}
+
+fn cell(factory: &TestFactory, machine: Entity) -> IVec2 {
+    factory.world().get::<GridPos>(machine).expect("Machine is on the grid").0
+}
+
+#[test]
+fn moving_keeps_the_machine_and_its_craft() {
+    let mut factory = TestFactory::new();
+    let producer = factory.producer(ItemType::Input);
+    let transformer = factory.transformer(ItemType::Storage);
+    factory.couple(producer, transformer, ItemType::Input);
+    factory.tick(25);
+    let (outputs, inputs, status) = (factory.outputs(producer), factory.inputs(transformer), factory.status(producer));
+    assert!(factory.is_working(producer));
+
+    move_machine(&mut factory, producer, IVec2::new(0, 20), Rotation::North);
+    assert_eq!(cell(&factory, producer), IVec2::new(0, 20));
+    assert_eq!((factory.outputs(producer), factory.inputs(transformer), factory.status(producer)), (outputs.clone(), inputs.clone(), status));
+
+    step_history(factory.world_mut(), true);
+    assert_eq!(cell(&factory, producer), IVec2::ZERO);
+    assert_eq!((factory.outputs(producer), factory.inputs(transformer), factory.status(producer)), (outputs, inputs, status));
+
+    // Still coupled, so the transformer keeps getting Inputs
+    let delivered = |factory: &TestFactory| factory.inputs(transformer)[0] + factory.stats(transformer).items_consumed;
+    let before = delivered(&factory);
+    factory.tick(30);
+    assert_eq!(delivered(&factory), before + 3);
+}
+
+#[test]
+fn undoing_a_recipe_change_neither_makes_nor_loses_items() {
+    let mut factory = TestFactory::new();
+    let producer = factory.producer(ItemType::Input);
+    let transformer = factory.transformer(ItemType::Storage);
+    factory.couple(producer, transformer, ItemType::Input);
+    factory.fill(transformer, ItemType::Input, 3);
+    let stock = factory.world().resource::<Inventory>().count(ItemType::Input);
+    let other = factory.recipes().get_transformer(ItemType::Producer).expect("There's a transformer recipe for it");
+
+    // The new recipe takes Outputs, so the Inputs go to the Inventory
+    set_recipe(&mut factory, transformer, other);
+    assert_eq!(factory.inputs(transformer), [0]);
+    assert_eq!(factory.world().resource::<Inventory>().count(ItemType::Input), stock + 3);
+
+    // Undoing doesn't take them back out, nor make new ones, but it does couple the producer back
+    step_history(factory.world_mut(), true);
+    assert_eq!(*factory.world().get::<Recipe>(transformer).expect("Machine has a recipe"), factory.recipes().get_transformer(ItemType::Storage).expect("There's a transformer recipe for it"));
+    assert_eq!(factory.inputs(transformer), [0]);
+    assert_eq!(factory.world().resource::<Inventory>().count(ItemType::Input), stock + 3);
+    factory.tick(10);
+    assert_eq!(factory.inputs(transformer), [1]);
+
+    step_history(factory.world_mut(), false);
+    assert_eq!(*factory.world().get::<Recipe>(transformer).expect("Machine has a recipe"), other);
+    assert_eq!(factory.world().resource::<Inventory>().count(ItemType::Input), stock + 4);
+}
@@ -0,0 +1,181 @@
+mod common;
+
+use bevy::prelude::*;
+use common::TestFactory;
+use factory::{objectives::Deliveries, pipeline::{circuit::{add_sensor, Channel, Comparison, EnableCondition, SensorReading}, fast_forward::{fast_forward, fast_forward_within}, machine::{MachineKind, MachineStatus, Working}, recipe::{ItemStack, Recipe}}, ItemType};
+use proptest::{prelude::*, sample::Index};
+
+/// An hour of ticks
+const TICKS: u64 = 36_000;
+
+/// Producers feeding a combinator chain into a sink, with a side line that backs up
+fn build() -> (TestFactory, Vec<Entity>) {
+    let mut factory = TestFactory::new();
+    let inputs = factory.producer(ItemType::Input);
+    let outputs = factory.producer(ItemType::Output);
+    let more_inputs = factory.producer(ItemType::Input);
+    let first = factory.combinator(ItemType::Transformer);
+    let second = factory.combinator(ItemType::Combinator);
+    let sink = factory.sink(ItemType::Combinator);
+    factory.couple(inputs, first, ItemType::Input);
+    factory.couple(outputs, first, ItemType::Output);
+    factory.couple(first, second, ItemType::Transformer);
+    factory.couple(more_inputs, second, ItemType::Input);
+    factory.couple(second, sink, ItemType::Combinator);
+
+    let batch_producer = factory.producer(ItemType::Input);
+    let transformer = factory.transformer(ItemType::Storage);
+    factory.set_mult(batch_producer, 4);
+    factory.couple(batch_producer, transformer, ItemType::Input);
+
+    (factory, vec![inputs, outputs, more_inputs, first, second, sink, batch_producer, transformer])
+}
+
+/// Runs `ticks` on `ticked` and fast-forwards `skipped` through them, then checks they came out within the
+/// documented tolerance: 1% plus two batches of `batch` items, and buffers within `buffer` items
+fn assert_close(mut ticked: TestFactory, mut skipped: TestFactory, machines: &[Entity], ticks: u64, batch: u64, buffer: u64) {
+    ticked.tick(ticks);
+    fast_forward(skipped.world_mut(), ticks);
+    assert_eq!(skipped.tick_count(), ticked.tick_count());
+
+    let close = |a: u64, b: u64| a.abs_diff(b) <= a.max(b) / 100 + 2 * batch;
+    for item_type in [ItemType::Input, ItemType::Output, ItemType::Transformer, ItemType::Combinator, ItemType::Storage] {
+        let delivered = |factory: &TestFactory| factory.world().resource::<Deliveries>().count(item_type);
+        assert!(close(delivered(&ticked), delivered(&skipped)), "delivered {} vs {} {item_type:?}", delivered(&ticked), delivered(&skipped));
+    }
+
+    for machine in machines {
+        let (a, b) = (ticked.stats(*machine), skipped.stats(*machine));
+        assert!(close(a.crafts, b.crafts), "crafts {} vs {}", a.crafts, b.crafts);
+        assert!(close(a.items_consumed, b.items_consumed), "consumed {} vs {}", a.items_consumed, b.items_consumed);
+        assert!(close(a.items_produced, b.items_produced), "produced {} vs {}", a.items_produced, b.items_produced);
+
+        for (a, b) in ticked.inputs(*machine).into_iter().zip(skipped.inputs(*machine)).chain(ticked.outputs(*machine).into_iter().zip(skipped.outputs(*machine))) {
+            assert!(a.abs_diff(b) <= buffer, "buffer {a} vs {b}");
+        }
+    }
+}
+
+#[test]
+fn fast_forward_matches_ticking_within_tolerance() {
+    let (mut ticked, machines) = build();
+    let (mut skipped, _) = build();
+    ticked.tick(25);
+    skipped.tick(25);
+
+    // Batches of at most 4, and 5 items per combinator or transformer craft on either side of a buffer
+    assert_close(ticked, skipped, &machines, TICKS, 4, 5);
+}
+
+/// build, with the first combinator kept from making more Transformers than the second one takes, like the default factory
+fn build_conditioned() -> (TestFactory, Vec<Entity>) {
+    let (mut factory, machines) = build();
+    let world = factory.world_mut();
+    add_sensor(&mut world.commands(), machines[3], SensorReading::OutputFill(ItemType::Transformer), Channel(0));
+    world.entity_mut(machines[3]).insert(EnableCondition { channel: Channel(0), comparison: Comparison::Less, value: 20 });
+    world.flush();
+    factory.tick(25);
+    (factory, machines)
+}
+
+#[test]
+fn enable_conditions_fast_forward_within_tolerance() {
+    let (ticked, machines) = build_conditioned();
+    let (skipped, _) = build_conditioned();
+    // The condition holds back the first combinator by a batch or so on top
+    assert_close(ticked, skipped, &machines, TICKS, 4, 5);
+
+    // Worked out from the rates rather than stepped through
+    let (mut factory, _) = build_conditioned();
+    assert_eq!(fast_forward_within(factory.world_mut(), TICKS, 100), 0);
+}
+
+#[test]
+fn short_skips_step_through_a_few_ticks_at_a_time() {
+    let (mut factory, _) = build();
+    let start = factory.tick_count();
+    assert_eq!(fast_forward_within(factory.world_mut(), 50, 20), 30);
+    assert_eq!(factory.tick_count(), start + 20);
+    assert_eq!(fast_forward_within(factory.world_mut(), 30, 20), 10);
+    assert_eq!(fast_forward_within(factory.world_mut(), 10, 20), 0);
+    assert_eq!(factory.tick_count(), start + 50);
+}
+
+#[test]
+fn crafts_in_progress_carry_into_the_skip() {
+    let mut factory = TestFactory::new();
+    let slow = factory.machine(Recipe::new(MachineKind::Transformer, &[ItemStack::new(ItemType::Input, 1)], &[ItemStack::new(ItemType::Output, 1)], 500));
+    factory.fill(slow, ItemType::Input, 1);
+    factory.tick(1);
+    assert_eq!(factory.status(slow), MachineStatus::Working(Working { finishes_at: 500, amount: 1 }));
+
+    // Still going after skipping part of the way
+    fast_forward(factory.world_mut(), 300);
+    assert_eq!(factory.status(slow), MachineStatus::Working(Working { finishes_at: 500, amount: 1 }));
+    assert_eq!(factory.outputs(slow), [0]);
+
+    // Done on time, and not before
+    fast_forward(factory.world_mut(), 300);
+    assert_eq!(factory.outputs(slow), [1]);
+    assert_eq!(factory.stats(slow).crafts, 1);
+    assert_eq!(factory.status(slow), MachineStatus::Idle);
+}
+
+#[derive(Clone, Debug)]
+/// A factory like the fuzz tests build: machines in a row, coupled by position for every item type they share
+struct Layout {
+    /// Recipe and Mult of each machine
+    machines: Vec<(Index, u64)>,
+    couplings: Vec<(Index, Index)>,
+    /// Ticks run before the skip, so it starts with crafts part done and buffers part full
+    warm_up: u64,
+}
+
+fn layout() -> impl Strategy<Value = Layout> {
+    (
+        prop::collection::vec((any::<Index>(), 1..=4u64), 1..10),
+        prop::collection::vec((any::<Index>(), any::<Index>()), 0..20),
+        1..300u64,
+    ).prop_map(|(machines, couplings, warm_up)| Layout { machines, couplings, warm_up })
+}
+
+fn build_layout(layout: &Layout) -> (TestFactory, Vec<Entity>) {
+    let mut factory = TestFactory::new();
+    let recipes = factory.recipes().inner.clone();
+    let machines: Vec<Entity> = layout.machines.iter().map(|(recipe, mult)| {
+        let machine = factory.machine(*recipe.get(&recipes));
+        factory.set_mult(machine, *mult);
+        machine
+    }).collect();
+
+    for (src, dest) in &layout.couplings {
+        let (src, dest) = (*src.get(&machines), *dest.get(&machines));
+        let (src_recipe, dest_recipe) = (factory.world().get(src).copied().unwrap_or(recipes[0]), factory.world().get(dest).copied().unwrap_or(recipes[0]));
+        for output in src_recipe.outputs.iter().flatten() {
+            if dest_recipe.inputs.iter().flatten().any(|input| input.item_type == output.item_type) {
+                factory.try_couple(src, dest, output.item_type);
+            }
+        }
+    }
+
+    factory.tick(layout.warm_up);
+    (factory, machines)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(12))]
+
+    #[test]
+    fn random_factories_fast_forward_within_tolerance(layout in layout()) {
+        let (ticked, machines) = build_layout(&layout);
+        let (skipped, _) = build_layout(&layout);
+        let recipes = ticked.recipes().inner.clone();
+        let batch = layout.machines.iter().map(|(recipe, mult)| {
+            let recipe = recipe.get(&recipes);
+            recipe.inputs.iter().chain(recipe.outputs.iter()).flatten().map(|stack| stack.amount).max().unwrap_or(1) * mult
+        }).max().unwrap_or(1);
+
+        // Long enough for buffers to saturate and drain
+        assert_close(ticked, skipped, &machines, 3000, batch, 2 * batch);
+    }
+}
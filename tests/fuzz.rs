@@ -0,0 +1,89 @@
+//! Random factories built from the base recipes, run for random numbers of ticks. proptest shrinks failing
+//! layouts down to the fewest machines, couplings and ticks that still fail, and saves them under proptest-regressions,
+//! which stays out of git. The short runs go with every `cargo test`, the long ones with `cargo test --test fuzz -- --ignored`
+
+mod common;
+
+use bevy::prelude::*;
+use common::TestFactory;
+use factory::{objectives::Deliveries, pipeline::machine::{MachineStats, MachineStatus}, plugin::FactorySet, update_labels, ItemType};
+use proptest::{prelude::*, sample::Index};
+
+#[derive(Clone, Debug)]
+/// A factory to build: machines in a row, then couplings between them by position
+struct Layout {
+    /// Recipe and Mult of each machine
+    machines: Vec<(Index, u64)>,
+    /// Source and destination machines, coupled for every item type one makes and the other takes
+    couplings: Vec<(Index, Index)>,
+    ticks: u64,
+}
+
+/// Up to `max_ticks`, enough for short runs to fill the default buffers of 50 and back up
+fn layout(max_ticks: u64) -> impl Strategy<Value = Layout> {
+    (
+        prop::collection::vec((any::<Index>(), 1..=4u64), 1..12),
+        prop::collection::vec((any::<Index>(), any::<Index>()), 0..24),
+        1..max_ticks,
+    ).prop_map(|(machines, couplings, ticks)| Layout { machines, couplings, ticks })
+}
+
+/// Everything about a machine that the simulation changes
+type MachineState = (Vec<u64>, Vec<u64>, MachineStatus, MachineStats);
+
+/// Builds and runs `layout`, checking conservation every tick, and returns where every machine ended up
+fn run(layout: &Layout) -> (Vec<MachineState>, Vec<(ItemType, u64)>) {
+    let mut factory = TestFactory::new();
+    // Labels aren't part of the headless plugin, but they unwrap their text entities every tick
+    factory.app.add_systems(FixedUpdate, update_labels.in_set(FactorySet::Display));
+
+    let recipes = factory.recipes().inner.clone();
+    let machines: Vec<Entity> = layout.machines.iter().map(|(recipe, mult)| {
+        let machine = factory.machine(*recipe.get(&recipes));
+        factory.set_mult(machine, *mult);
+        machine
+    }).collect();
+
+    for (src, dest) in &layout.couplings {
+        let (src, dest) = (*src.get(&machines), *dest.get(&machines));
+        let (src_recipe, dest_recipe) = (factory.world().get(src).copied().unwrap_or(recipes[0]), factory.world().get(dest).copied().unwrap_or(recipes[0]));
+        for output in src_recipe.outputs.iter().flatten() {
+            if dest_recipe.inputs.iter().flatten().any(|input| input.item_type == output.item_type) {
+                factory.try_couple(src, dest, output.item_type);
+            }
+        }
+    }
+
+    factory.tick(layout.ticks);
+    let states = machines.iter().map(|machine| (factory.inputs(*machine), factory.outputs(*machine), factory.status(*machine), factory.stats(*machine))).collect();
+    let deliveries = factory.world().resource::<Deliveries>();
+    let delivered = recipes.iter().flat_map(|recipe| recipe.inputs.iter().flatten()).map(|input| (input.item_type, deliveries.count(input.item_type))).collect();
+    (states, delivered)
+}
+
+/// Runs `layout` twice, it has to conserve items both times and end up the same
+fn reruns_the_same(layout: &Layout) -> Result<(), TestCaseError> {
+    let first = run(layout);
+    let second = run(layout);
+    prop_assert_eq!(first, second);
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn random_factories_conserve_items_and_rerun_the_same(layout in layout(600)) {
+        reruns_the_same(&layout)?;
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(48))]
+
+    #[test]
+    #[ignore = "slow, run with --ignored"]
+    fn long_running_random_factories_conserve_items_and_rerun_the_same(layout in layout(1500)) {
+        reruns_the_same(&layout)?;
+    }
+}
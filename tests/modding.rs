@@ -0,0 +1,75 @@
+use bevy::prelude::*;
+use factory::{modding::{CustomItem, CustomMachine, FactoryAppExt, Registry}, pipeline::{machine::MachineKind, recipe::{ItemStack, Recipe, Recipes}}, plugin::FactoryPlugin, research::Research, save::SaveGame, ItemType};
+
+static WIDGET: CustomItem = CustomItem { name: "Widget", color: Color::WHITE };
+
+static PRESS: CustomMachine = CustomMachine { name: "Press", footprint: UVec2::new(2, 2), item: None };
+
+fn press_recipe() -> Recipe {
+    Recipe::new(MachineKind::Custom(&PRESS), &[ItemStack::new(ItemType::Input, 2)], &[ItemStack::new(ItemType::Custom(&WIDGET), 1)], 10)
+}
+
+struct PressMod;
+
+impl Plugin for PressMod {
+    fn build(&self, app: &mut App) {
+        app.add_item(&WIDGET)
+            .add_machine_kind(&PRESS)
+            .add_recipe(press_recipe());
+    }
+}
+
+#[test]
+fn mods_can_be_added_before_factory_plugin() {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, PressMod, FactoryPlugin::headless()));
+
+    let recipes = app.world().resource::<Recipes>();
+    assert!(recipes.inner.contains(&press_recipe()), "The mod's recipe is kept");
+    assert!(recipes.get_producer(ItemType::Input).is_some(), "The built-in recipes are there too");
+    assert!(app.world().resource::<Research>().is_unlocked(&press_recipe()));
+    assert!(app.world().resource::<Registry>().knows_machine(MachineKind::Custom(&PRESS)));
+}
+
+/// FactoryPlugin with PressMod added after it
+fn modded_app() -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, FactoryPlugin::headless(), PressMod));
+    app
+}
+
+#[test]
+fn mods_can_be_added_after_factory_plugin() {
+    let app = modded_app();
+
+    assert!(app.world().resource::<Recipes>().inner.contains(&press_recipe()));
+    assert!(app.world().resource::<Research>().is_unlocked(&press_recipe()));
+}
+
+#[test]
+fn custom_items_round_trip_through_saves() {
+    let mut app = modded_app();
+    app.world_mut().resource_mut::<Research>().progress.insert(ItemType::Custom(&WIDGET), 3);
+    let text = ron::to_string(&SaveGame::capture(app.world())).unwrap();
+    assert!(text.contains("Custom(\"Widget\")"), "Saved by name: {text}");
+
+    let mut loaded = modded_app();
+    SaveGame::parse(&text, loaded.world().resource::<Registry>()).unwrap().apply(loaded.world_mut());
+    assert_eq!(loaded.world().resource::<Research>().progress.get(&ItemType::Custom(&WIDGET)), Some(&3));
+
+    let mut unmodded = App::new();
+    unmodded.add_plugins((MinimalPlugins, FactoryPlugin::headless()));
+    let err = SaveGame::parse(&text, unmodded.world().resource::<Registry>()).unwrap_err();
+    assert!(err.to_string().contains("unknown item Widget"), "{err}");
+}
+
+#[test]
+fn custom_machine_kinds_round_trip() {
+    let app = modded_app();
+    let registry = app.world().resource::<Registry>();
+
+    let text = ron::to_string(&MachineKind::Custom(&PRESS)).unwrap();
+    assert_eq!(text, "Custom(\"Press\")");
+    assert_eq!(registry.loading(|| ron::from_str::<MachineKind>(&text)).unwrap(), MachineKind::Custom(&PRESS));
+    assert!(ron::from_str::<MachineKind>(&text).is_err(), "Custom machine kinds need a Registry to load");
+}
@@ -0,0 +1,173 @@
+mod common;
+
+use common::TestFactory;
+use factory::{pipeline::{machine::{MachineKind, MachineStatus, Working}, recipe::{ItemStack, Recipe}}, ItemType};
+
+#[test]
+fn producer_crafts_once_per_recipe() {
+    let mut factory = TestFactory::new();
+    let producer = factory.producer(ItemType::Input);
+
+    factory.tick(9);
+    assert_eq!(factory.outputs(producer), [0]);
+    assert_eq!(factory.status(producer), MachineStatus::Working(Working { finishes_at: 10, amount: 1 }));
+
+    factory.tick(1);
+    assert_eq!(factory.outputs(producer), [1]);
+    factory.tick(10);
+    assert_eq!(factory.outputs(producer), [2]);
+    assert_eq!(factory.stats(producer).crafts, 2);
+}
+
+#[test]
+fn transformer_takes_inputs_when_it_starts() {
+    let mut factory = TestFactory::new();
+    let transformer = factory.transformer(ItemType::Storage);
+    factory.fill(transformer, ItemType::Input, 7);
+
+    factory.tick(1);
+    assert_eq!(factory.inputs(transformer), [2]);
+    assert!(factory.is_working(transformer));
+
+    factory.tick(19);
+    assert_eq!(factory.outputs(transformer), [1]);
+    // Two left over isn't enough for another craft
+    factory.tick(50);
+    assert_eq!(factory.inputs(transformer), [2]);
+    assert_eq!(factory.outputs(transformer), [1]);
+    assert_eq!(factory.status(transformer), MachineStatus::LacksInput);
+}
+
+#[test]
+fn coupled_producer_feeds_transformer() {
+    let mut factory = TestFactory::new();
+    let producer = factory.producer(ItemType::Input);
+    let transformer = factory.transformer(ItemType::Storage);
+    factory.couple(producer, transformer, ItemType::Input);
+
+    // The fifth Input arrives on tick 50, the craft starts on tick 51 and takes 20 ticks
+    factory.tick(50);
+    assert_eq!(factory.outputs(producer), [0]);
+    assert_eq!(factory.inputs(transformer), [5]);
+
+    factory.tick(19);
+    assert_eq!(factory.outputs(transformer), [0]);
+    factory.tick(1);
+    assert_eq!(factory.outputs(transformer), [1]);
+}
+
+#[test]
+fn combinator_waits_for_every_input() {
+    let mut factory = TestFactory::new();
+    let combinator = factory.combinator(ItemType::Transformer);
+    factory.fill(combinator, ItemType::Input, 5);
+
+    factory.tick(30);
+    assert_eq!(factory.inputs(combinator), [5, 0]);
+    assert_eq!(factory.status(combinator), MachineStatus::LacksInput);
+
+    factory.fill(combinator, ItemType::Output, 5);
+    factory.tick(20);
+    assert_eq!(factory.inputs(combinator), [0, 0]);
+    assert_eq!(factory.outputs(combinator), [1]);
+}
+
+#[test]
+fn combinator_chain_delivers_to_sink() {
+    let mut factory = TestFactory::new();
+    let inputs = factory.producer(ItemType::Input);
+    let outputs = factory.producer(ItemType::Output);
+    let combinator = factory.combinator(ItemType::Transformer);
+    let sink = factory.sink(ItemType::Transformer);
+    factory.couple(inputs, combinator, ItemType::Input);
+    factory.couple(outputs, combinator, ItemType::Output);
+    factory.couple(combinator, sink, ItemType::Transformer);
+
+    // Both producers have made 5 by tick 50, the craft runs from tick 51 to 70 and is pushed to the sink right away
+    factory.tick(70);
+    assert_eq!(factory.outputs(combinator), [0]);
+    assert_eq!(factory.stats(sink).items_consumed, 1);
+}
+
+#[test]
+fn mult_crafts_in_batches() {
+    let mut factory = TestFactory::new();
+    let transformer = factory.transformer(ItemType::Storage);
+    factory.set_mult(transformer, 3);
+    factory.fill(transformer, ItemType::Input, 15);
+
+    factory.tick(1);
+    assert_eq!(factory.status(transformer), MachineStatus::Working(Working { finishes_at: 20, amount: 3 }));
+    assert_eq!(factory.inputs(transformer), [0]);
+
+    factory.tick(19);
+    assert_eq!(factory.outputs(transformer), [3]);
+    assert_eq!(factory.stats(transformer).crafts, 3);
+}
+
+#[test]
+fn mult_batch_is_cut_down_to_inputs() {
+    let mut factory = TestFactory::new();
+    let transformer = factory.transformer(ItemType::Storage);
+    factory.set_mult(transformer, 3);
+    factory.fill(transformer, ItemType::Input, 12);
+
+    factory.tick(20);
+    assert_eq!(factory.outputs(transformer), [2]);
+    assert_eq!(factory.inputs(transformer), [2]);
+}
+
+#[test]
+fn full_output_stops_producer() {
+    let mut factory = TestFactory::new();
+    let producer = factory.producer(ItemType::Input);
+
+    factory.tick(500);
+    assert_eq!(factory.outputs(producer), [50]);
+
+    factory.tick(100);
+    assert_eq!(factory.outputs(producer), [50]);
+    assert_eq!(factory.stats(producer).crafts, 50);
+    assert_eq!(factory.status(producer), MachineStatus::Full);
+}
+
+#[test]
+fn backpressure_fills_every_buffer_upstream() {
+    let mut factory = TestFactory::new();
+    let producer = factory.producer(ItemType::Input);
+    let transformer = factory.transformer(ItemType::Storage);
+    factory.couple(producer, transformer, ItemType::Input);
+
+    // Nothing takes the transformer's output, so it fills, then its input, then the producer's output
+    factory.tick(5000);
+    assert_eq!(factory.outputs(transformer), [50]);
+    assert_eq!(factory.inputs(transformer), [50]);
+    assert_eq!(factory.outputs(producer), [50]);
+    assert_eq!(factory.stats(transformer).crafts, 50);
+    assert_eq!(factory.stats(producer).crafts, 50 + 50 + 50 * 5);
+
+    factory.tick(100);
+    assert_eq!(factory.stats(producer).crafts, 350);
+}
+
+#[test]
+fn same_item_inputs_fill_separately() {
+    let mut factory = TestFactory::new();
+    let left = factory.producer(ItemType::Input);
+    let right = factory.producer(ItemType::Input);
+    let combinator = factory.machine(Recipe::new(MachineKind::Combinator, &[ItemStack::new(ItemType::Input, 5), ItemStack::new(ItemType::Input, 5)], &[ItemStack::new(ItemType::Transformer, 1)], 10));
+    factory.couple(left, combinator, ItemType::Input);
+
+    // Everything goes into the first connector, the second one still holds nothing to craft with
+    factory.tick(200);
+    assert_eq!(factory.inputs(combinator), [20, 0]);
+    assert_eq!(factory.stats(combinator).crafts, 0);
+
+    // The right producer's backed up outputs arrive, then the next craft takes 5 from each connector
+    factory.couple(right, combinator, ItemType::Input);
+    factory.tick(1);
+    assert_eq!(factory.inputs(combinator), [20, 20]);
+    factory.tick(1);
+    assert_eq!(factory.inputs(combinator), [15, 15]);
+    assert!(factory.is_working(combinator));
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use bevy::prelude::*;
+use common::TestFactory;
+use factory::{inventory::Inventory, modding::Registry, objectives::Deliveries, pipeline::{circuit::{add_sensor, Channel, Comparison, EnableCondition, SensorReading}, machine::{MachineId, MachineKind}}, save::SaveGame, ItemType};
+
+/// Producers feeding a combinator into a sink, the combinator held back by a sensor on its output
+fn build() -> TestFactory {
+    let mut factory = TestFactory::new();
+    let inputs = factory.producer(ItemType::Input);
+    let outputs = factory.producer(ItemType::Output);
+    let combinator = factory.combinator(ItemType::Transformer);
+    let sink = factory.sink(ItemType::Transformer);
+    factory.set_mult(outputs, 2);
+    factory.couple(inputs, combinator, ItemType::Input);
+    factory.couple(outputs, combinator, ItemType::Output);
+    factory.couple(combinator, sink, ItemType::Transformer);
+
+    let world = factory.world_mut();
+    add_sensor(&mut world.commands(), combinator, SensorReading::InputFill(ItemType::Output), Channel(0));
+    world.entity_mut(combinator).insert(EnableCondition { channel: Channel(0), comparison: Comparison::Greater, value: 4 });
+    world.flush();
+    factory
+}
+
+/// The factory's machines, oldest first
+fn machines(factory: &mut TestFactory) -> Vec<Entity> {
+    let mut machines: Vec<(MachineId, Entity)> = factory.world_mut().query_filtered::<(&MachineId, Entity), With<MachineKind>>().iter(factory.world()).map(|(id, machine)| (*id, machine)).collect();
+    machines.sort_by_key(|(MachineId(id), _)| *id);
+    machines.into_iter().map(|(_, machine)| machine).collect()
+}
+
+#[test]
+fn loading_a_save_restores_the_factory() {
+    let mut saved = build();
+    saved.tick(37);
+    let text = ron::to_string(&SaveGame::capture(saved.world())).unwrap();
+
+    // Loads over whatever factory is there
+    let mut loaded = TestFactory::new();
+    loaded.producer(ItemType::Input);
+    SaveGame::parse(&text, loaded.world().resource::<Registry>()).unwrap().apply(loaded.world_mut());
+    assert_eq!(loaded.tick_count(), saved.tick_count());
+
+    // Carries on exactly where it left off, crafts in progress, couplings and enable condition included
+    saved.tick(200);
+    loaded.tick(200);
+    let (saved_machines, loaded_machines) = (machines(&mut saved), machines(&mut loaded));
+    assert_eq!(saved_machines.len(), loaded_machines.len());
+    for (a, b) in saved_machines.into_iter().zip(loaded_machines) {
+        assert_eq!(saved.stats(a), loaded.stats(b));
+        assert_eq!(saved.status(a), loaded.status(b));
+        assert_eq!((saved.inputs(a), saved.outputs(a)), (loaded.inputs(b), loaded.outputs(b)));
+    }
+    assert_eq!(saved.world().resource::<Deliveries>().count(ItemType::Transformer), loaded.world().resource::<Deliveries>().count(ItemType::Transformer));
+    assert_eq!(saved.world().resource::<Inventory>().iter().collect::<Vec<_>>(), loaded.world().resource::<Inventory>().iter().collect::<Vec<_>>());
+    assert!(loaded.violations().is_empty(), "{:?}", loaded.violations());
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use common::TestFactory;
+use factory::{pipeline::{machine::MachineKind, recipe::{ItemStack, Recipe}, validation::{DeadlockDetector, GraphIssue, DEADLOCK_TICKS}}, ItemType};
+
+/// A transformer turning `input` into `output`, `amount` at a time
+fn converter(input: ItemType, output: ItemType, amount: u64) -> Recipe {
+    Recipe::new(MachineKind::Transformer, &[ItemStack::new(input, 1)], &[ItemStack::new(output, amount)], 10)
+}
+
+#[test]
+fn coupled_line_has_no_issues() {
+    let mut factory = TestFactory::new();
+    let producer = factory.producer(ItemType::Input);
+    let transformer = factory.transformer(ItemType::Storage);
+    let sink = factory.sink(ItemType::Storage);
+    factory.couple(producer, transformer, ItemType::Input);
+    factory.couple(transformer, sink, ItemType::Storage);
+
+    assert_eq!(factory.issues(), []);
+}
+
+#[test]
+fn missing_supply_and_consumer_spread_along_the_line() {
+    let mut factory = TestFactory::new();
+    let transformer = factory.transformer(ItemType::Storage);
+    let sink = factory.sink(ItemType::Storage);
+    let producer = factory.producer(ItemType::Input);
+    let storage = factory.transformer(ItemType::Storage);
+    factory.couple(transformer, sink, ItemType::Storage);
+    factory.couple(producer, storage, ItemType::Input);
+
+    let issues = factory.issues();
+    assert!(issues.contains(&GraphIssue::UnsuppliedInput { machine: transformer, item_type: ItemType::Input, via: None }));
+    assert!(issues.contains(&GraphIssue::UnsuppliedInput { machine: sink, item_type: ItemType::Storage, via: Some(transformer) }));
+    assert!(issues.contains(&GraphIssue::UnconsumedOutput { machine: storage, item_type: ItemType::Storage, via: None }));
+    assert!(issues.contains(&GraphIssue::UnconsumedOutput { machine: producer, item_type: ItemType::Input, via: Some(storage) }));
+    assert_eq!(issues.len(), 4);
+}
+
+#[test]
+fn loop_is_flagged() {
+    let mut factory = TestFactory::new();
+    let first = factory.machine(converter(ItemType::Input, ItemType::Output, 1));
+    let second = factory.machine(converter(ItemType::Output, ItemType::Input, 1));
+    factory.couple(first, second, ItemType::Output);
+    factory.couple(second, first, ItemType::Input);
+
+    let issues = factory.issues();
+    let Some(GraphIssue::Cycle(machines)) = issues.iter().find(|issue| matches!(issue, GraphIssue::Cycle(_))) else { panic!("The loop is found in {issues:?}") };
+    assert_eq!(machines.len(), 2);
+    assert!(machines.contains(&first) && machines.contains(&second));
+}
+
+#[test]
+fn empty_loop_deadlocks() {
+    let mut factory = TestFactory::new();
+    let first = factory.machine(converter(ItemType::Input, ItemType::Output, 1));
+    let second = factory.machine(converter(ItemType::Output, ItemType::Input, 1));
+    factory.couple(first, second, ItemType::Output);
+    factory.couple(second, first, ItemType::Input);
+
+    // Neither has anything to start with, each waits for the other's output from the first tick on
+    factory.tick(DEADLOCK_TICKS);
+    assert!(factory.deadlocks().is_empty());
+    factory.tick(1);
+    let deadlocks = factory.deadlocks();
+    assert_eq!(deadlocks.len(), 1);
+    assert!(deadlocks[0].machines.contains(&first) && deadlocks[0].machines.contains(&second));
+
+    // Reported once, not every tick it lasts
+    factory.tick(DEADLOCK_TICKS * 2);
+    assert_eq!(factory.deadlocks().len(), 1);
+}
+
+#[test]
+fn growing_loop_deadlocks_once_buffers_fill() {
+    let mut factory = TestFactory::new();
+    let doubler = factory.machine(converter(ItemType::Input, ItemType::Output, 2));
+    let returner = factory.machine(converter(ItemType::Output, ItemType::Input, 1));
+    factory.couple(doubler, returner, ItemType::Output);
+    factory.couple(returner, doubler, ItemType::Input);
+    factory.fill(doubler, ItemType::Input, 1);
+
+    // Items circulate and double until every buffer in the loop is full
+    factory.tick(DEADLOCK_TICKS);
+    assert!(factory.deadlocks().is_empty());
+    factory.tick(5000);
+    let deadlocks = factory.deadlocks();
+    assert_eq!(deadlocks.len(), 1);
+    assert!(factory.outputs(doubler)[0] > 0 && factory.outputs(returner)[0] > 0);
+}
+
+#[test]
+fn loop_that_keeps_moving_isnt_deadlocked() {
+    let mut factory = TestFactory::new();
+    let first = factory.machine(converter(ItemType::Input, ItemType::Output, 1));
+    let second = factory.machine(converter(ItemType::Output, ItemType::Input, 1));
+    factory.couple(first, second, ItemType::Output);
+    factory.couple(second, first, ItemType::Input);
+    factory.fill(first, ItemType::Input, 3);
+
+    factory.tick(DEADLOCK_TICKS * 3);
+    assert!(factory.deadlocks().is_empty());
+}
+
+#[test]
+fn deadlock_ticks_can_change_while_running() {
+    let mut factory = TestFactory::new();
+    factory.world_mut().resource_mut::<DeadlockDetector>().ticks = DEADLOCK_TICKS * 2;
+    let first = factory.machine(converter(ItemType::Input, ItemType::Output, 1));
+    let second = factory.machine(converter(ItemType::Output, ItemType::Input, 1));
+    factory.couple(first, second, ItemType::Output);
+    factory.couple(second, first, ItemType::Input);
+    factory.tick(1);
+
+    // Lowered after the machines were queued for the longer wait, they're still found on time
+    factory.world_mut().resource_mut::<DeadlockDetector>().ticks = DEADLOCK_TICKS;
+    let third = factory.machine(converter(ItemType::Storage, ItemType::Separator, 1));
+    let fourth = factory.machine(converter(ItemType::Separator, ItemType::Storage, 1));
+    factory.couple(third, fourth, ItemType::Separator);
+    factory.couple(fourth, third, ItemType::Storage);
+    factory.tick(DEADLOCK_TICKS + 1);
+    assert_eq!(factory.deadlocks().len(), 1);
+    assert!(factory.deadlocks()[0].machines.contains(&third));
+
+    factory.tick(DEADLOCK_TICKS);
+    assert_eq!(factory.deadlocks().len(), 2);
+}